@@ -7,6 +7,7 @@ use linefeed::{Interface, ReadResult};
 
 use runners::client::*;
 use runners::common::*;
+use runners::local::{send_control, Attach, Batch};
 use runners::server::JobId;
 // imports:1 ends here
 
@@ -46,6 +47,22 @@ pub enum Action {
         id: Option<JobId>,
     },
 
+    /// Show processes of a job running in the server.
+    #[structopt(name = "ps")]
+    Ps {
+        /// Job id
+        #[structopt(name = "JOB-ID")]
+        id: JobId,
+    },
+
+    /// Show status of a job in the server.
+    #[structopt(name = "status", alias = "st")]
+    Status {
+        /// Job id
+        #[structopt(name = "JOB-ID")]
+        id: JobId,
+    },
+
     /// Request to delete a job from the server.
     #[structopt(name = "delete", alias = "del")]
     Delete {
@@ -140,13 +157,34 @@ impl Command {
                 let _ = f.read_to_string(&mut buf)?;
                 client.create_job(&buf)?;
             }
+            Action::Ps { id } => {
+                let client = self.client()?;
+                let procs = client.list_job_processes(*id)?;
+                println!(
+                    "{:>8} {:>8} {:>4} {:>10} COMMAND",
+                    "PID", "PPID", "STAT", "RSS(kB)"
+                );
+                for p in procs {
+                    println!(
+                        "{:>8} {:>8} {:>4} {:>10} {}",
+                        p.pid, p.ppid, p.state, p.rss, p.cmdline
+                    );
+                }
+            }
+            Action::Status { id } => {
+                let client = self.client()?;
+                let info = client.job_status(*id)?;
+                println!("{:#?}", info);
+            }
             Action::Delete { id } => {
                 let client = self.client()?;
                 client.delete_job(*id)?;
             }
             Action::Wait { id } => {
                 let client = self.client()?;
-                client.wait_job(*id)?;
+                if let Some(usage) = client.wait_job(*id)? {
+                    println!("{:?}", usage);
+                }
             }
             Action::Get { file_name, id } => {
                 let client = self.client()?;
@@ -179,10 +217,82 @@ impl Command {
 }
 // core:1 ends here
 
+// modes
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*modes][modes:1]]
+/// Supervise a running program with timeout and graceful termination
+#[derive(StructOpt, Debug)]
+struct AttachCli {
+    #[structopt(flatten)]
+    verbosity: Verbosity,
+
+    #[structopt(flatten)]
+    attach: Attach,
+}
+
+/// Send a command to a running runner through its control socket
+#[derive(StructOpt, Debug)]
+struct CtlCli {
+    /// Path to the control socket of the runner
+    #[structopt(name = "SOCKET", parse(from_os_str))]
+    socket: PathBuf,
+
+    /// pause, resume, extend-timeout SECONDS, terminate or status
+    #[structopt(name = "COMMAND", raw(required = "true"))]
+    command: Vec<String>,
+}
+
+/// Run a list of commands in parallel
+#[derive(StructOpt, Debug)]
+struct BatchCli {
+    #[structopt(flatten)]
+    verbosity: Verbosity,
+
+    #[structopt(flatten)]
+    batch: Batch,
+}
+
+/// Run in a non-interactive mode if requested by the first argument.
+fn run_mode(mode: &str) -> Result<Option<i32>> {
+    // the mode name serves as the program name for argument parsing
+    let args = std::env::args().skip(1);
+    match mode {
+        "attach" => {
+            let args = AttachCli::from_iter(args);
+            args.verbosity.setup_env_logger(&env!("CARGO_PKG_NAME"))?;
+            let report = args.attach.run()?;
+            Ok(Some(report.code()))
+        }
+        "batch" => {
+            let args = BatchCli::from_iter(args);
+            args.verbosity.setup_env_logger(&env!("CARGO_PKG_NAME"))?;
+            let report = args.batch.run()?;
+            report.print_summary();
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            Ok(Some(report.code()))
+        }
+        "ctl" => {
+            let args = CtlCli::from_iter(args);
+            let reply = send_control(&args.socket, &args.command.join(" "))?;
+            println!("{}", reply.trim_end());
+            Ok(Some(if reply.starts_with("error:") { 1 } else { 0 }))
+        }
+        _ => Ok(None),
+    }
+}
+// modes:1 ends here
+
 // main
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*main][main:1]]
 fn main() -> CliResult {
+    if let Some(mode) = std::env::args().nth(1) {
+        if let Some(code) = run_mode(&mode)? {
+            // exit with the same code as the supervised program
+            std::process::exit(code);
+        }
+    }
+
     let interface = Interface::new("application runner client")?;
 
     let version = env!("CARGO_PKG_VERSION");
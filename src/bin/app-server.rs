@@ -13,18 +13,23 @@ pub struct Cli {
     /// Set application server address for binding.
     #[structopt(name = "ADDRESS")]
     address: Option<String>,
+
+    /// Max number of jobs running at the same time, with others queued. The
+    /// default is the number of online CPUs, not unlimited.
+    #[structopt(long = "slots", short = "j", value_name = "N")]
+    slots: Option<usize>,
 }
 
 fn main() -> Result<()> {
     let args = Cli::from_args();
     args.verbosity.setup_env_logger(&env!("CARGO_PKG_NAME"))?;
 
-    if let Some(addr) = args.address {
-        dbg!(&addr);
-        bind(&addr);
-    } else {
-        run();
-    }
+    let addr = args
+        .address
+        .unwrap_or_else(|| DEFAULT_SERVER_ADDRESS.into());
+    dbg!(&addr);
+    let slots = args.slots.unwrap_or_else(default_slots);
+    Server::new(&addr).with_slots(slots).serve();
 
     Ok(())
 }
@@ -23,8 +23,9 @@ fn main() -> Result<()> {
     let args = Cli::from_args();
     args.verbosity.setup_env_logger(&env!("CARGO_PKG_NAME"))?;
 
-    args.app.run()?;
+    let report = args.app.run()?;
 
-    Ok(())
+    // exit with the same code as the child program
+    std::process::exit(report.code());
 }
 // main:1 ends here
@@ -4,6 +4,7 @@
 use std::path::{Path, PathBuf};
 
 use crate::common::*;
+use crate::local::{ProcessInfo, ResourceUsage};
 use crate::server::*;
 // imports:1 ends here
 
@@ -54,15 +55,15 @@ impl Client {
         Ok(())
     }
 
-    /// Wait job to be done.
-    pub fn wait_job(&self, id: JobId) -> Result<()> {
+    /// Wait job to be done. Return resource usage of the job.
+    pub fn wait_job(&self, id: JobId) -> Result<Option<ResourceUsage>> {
         let url = format!("{}/jobs/{}", self.server_addr, id);
 
         // NOTE: the default request timeout is 30 seconds. Here we disable
         // timeout using reqwest builder.
         //
         // let new = reqwest::Client::new().get(&url).send()?;
-        let new = reqwest::Client::builder()
+        let mut new = reqwest::Client::builder()
             // .timeout(Duration::from_millis(500))
             .timeout(None)
             .build()
@@ -70,9 +71,10 @@ impl Client {
             .get(&url)
             .send()?;
 
-        dbg!(new);
+        dbg!(&new);
+        let usage = new.json()?;
 
-        Ok(())
+        Ok(usage)
     }
 
     /// Request server to create a job.
@@ -101,6 +103,32 @@ impl Client {
         Ok(())
     }
 
+    /// Request server to list processes of specified job `id`.
+    pub fn list_job_processes(&self, id: JobId) -> Result<Vec<ProcessInfo>> {
+        let url = format!("{}/jobs/{}/processes", self.server_addr, id);
+        let list = reqwest::get(&url)?.json()?;
+        Ok(list)
+    }
+
+    /// Request server to show status of specified job `id`.
+    pub fn job_status(&self, id: JobId) -> Result<JobInfo> {
+        let url = format!("{}/jobs/{}/status", self.server_addr, id);
+        let info = reqwest::get(&url)?.error_for_status()?.json()?;
+        Ok(info)
+    }
+
+    /// Request server to change priority of a queued job `id`.
+    pub fn set_job_priority(&self, id: JobId, priority: i32) -> Result<()> {
+        let url = format!("{}/jobs/{}/priority", self.server_addr, id);
+        reqwest::Client::new()
+            .put(&url)
+            .json(&priority)
+            .send()?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
     /// Download a job file from the server.
     pub fn get_job_file(&self, id: JobId, fname: &str) -> Result<()> {
         let url = format!("{}/jobs/{}/files/{}", self.server_addr, id, fname);
@@ -3,7 +3,11 @@
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*imports][imports:1]]
 use std::path::{Path, PathBuf};
 use std::process;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
 use crate::common::*;
@@ -13,16 +17,137 @@ use crate::common::*;
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*runner][runner:1]]
 /// A local runner that can make graceful exit
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 pub struct Runner {
     /// The program to be run.
     #[structopt(name = "program", parse(from_os_str))]
     program: PathBuf,
 
-    /// Job timeout in seconds
-    #[structopt(long = "timeout", short = "t")]
+    /// Job timeout in seconds, or in human-friendly form such as 2h30m
+    #[structopt(long = "timeout", short = "t", parse(try_from_str = "parse_duration"))]
     timeout: Option<u64>,
 
+    /// Terminate the program at DEADLINE, such as "2019-07-26 08:00" or
+    /// "08:00" for the next 8 o'clock
+    #[structopt(
+        long = "deadline",
+        value_name = "DEADLINE",
+        parse(try_from_str = "parse_deadline")
+    )]
+    deadline: Option<DateTime<Local>>,
+
+    /// Grace period in seconds before killing processes that survive
+    /// SIGTERM. The default is 30 seconds.
+    #[structopt(long = "kill-after", parse(try_from_str = "parse_duration"))]
+    kill_after: Option<u64>,
+
+    /// Write a run report in JSON format to FILE
+    #[structopt(long = "report", value_name = "FILE", parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    limits: ResourceLimits,
+
+    /// Terminate the program if it prints nothing within SECONDS
+    #[structopt(
+        long = "idle-timeout",
+        value_name = "SECONDS",
+        parse(try_from_str = "parse_duration")
+    )]
+    idle_timeout: Option<u64>,
+
+    /// Watch FILE instead of stdout/stderr of the program for idle timeout
+    #[structopt(
+        long = "watch",
+        value_name = "FILE",
+        parse(from_os_str),
+        raw(number_of_values = "1")
+    )]
+    watch_files: Vec<PathBuf>,
+
+    /// Number of retries when the program fails
+    #[structopt(long = "retries", value_name = "N", default_value = "0")]
+    retries: usize,
+
+    /// Delay in seconds before the first retry, which doubles for each
+    /// further retry
+    #[structopt(
+        long = "retry-delay",
+        value_name = "SECONDS",
+        default_value = "10",
+        parse(try_from_str = "parse_duration")
+    )]
+    retry_delay: u64,
+
+    /// Retry only when the program exits with one of CODES (comma separated)
+    #[structopt(
+        long = "retry-on-exit-codes",
+        value_name = "CODES",
+        raw(use_delimiter = "true", require_delimiter = "true")
+    )]
+    retry_on_exit_codes: Vec<i32>,
+
+    #[structopt(flatten)]
+    on_timeout: TimeoutHook,
+
+    /// Set environment variable for the program
+    #[structopt(
+        long = "env",
+        value_name = "KEY=VAL",
+        parse(try_from_str = "parse_env"),
+        raw(number_of_values = "1")
+    )]
+    envs: Vec<(String, String)>,
+
+    /// Clear inherited environment variables, except those set by --env
+    #[structopt(long = "clear-env")]
+    clear_env: bool,
+
+    /// Run the program in working directory DIR
+    #[structopt(long = "workdir", value_name = "DIR", parse(from_os_str))]
+    workdir: Option<PathBuf>,
+
+    /// Read stdin of the program from FILE, relative to working directory
+    #[structopt(long = "stdin", value_name = "FILE", parse(from_os_str))]
+    stdin: Option<PathBuf>,
+
+    /// Redirect stdout of the program to FILE, relative to working directory
+    #[structopt(long = "stdout", value_name = "FILE", parse(from_os_str))]
+    stdout: Option<PathBuf>,
+
+    /// Redirect stderr of the program to FILE, relative to working directory
+    #[structopt(long = "stderr", value_name = "FILE", parse(from_os_str))]
+    stderr: Option<PathBuf>,
+
+    /// Refuse to run if another runner holds the lock in working directory
+    #[structopt(long = "lock")]
+    lock: bool,
+
+    /// Wait for the lock in working directory instead of refusing to run
+    #[structopt(long = "wait-lock")]
+    wait_lock: bool,
+
+    /// Write run status in JSON format to FILE periodically
+    #[structopt(long = "status-file", value_name = "FILE", parse(from_os_str))]
+    status_file: Option<PathBuf>,
+
+    /// Interval in seconds for updating the status file
+    #[structopt(long = "status-interval", value_name = "SECONDS", default_value = "10")]
+    status_interval: u64,
+
+    #[structopt(flatten)]
+    forward_signals: ForwardSignals,
+
+    /// Accept control commands on a Unix domain socket at SOCKET: pause,
+    /// resume, extend-timeout SECONDS, terminate and status
+    #[structopt(long = "control-socket", value_name = "SOCKET", parse(from_os_str))]
+    control_socket: Option<PathBuf>,
+
+    /// Become a subreaper to track descendants escaped from the session, e.g.
+    /// by calling setsid or double forking, and terminate them as well.
+    #[structopt(long = "subreaper")]
+    subreaper: bool,
+
     /// Arguments that will be passed to `program`
     #[structopt(raw = true)]
     rest: Vec<String>,
@@ -30,7 +155,7 @@ pub struct Runner {
 
 impl Runner {
     /// Run program
-    pub fn run(&self) -> Result<()> {
+    pub fn run(&self) -> Result<RunReport> {
         run(&self)
     }
 
@@ -39,6 +164,29 @@ impl Runner {
             program: program.as_ref().into(),
             rest: vec![],
             timeout: None,
+            deadline: None,
+            kill_after: None,
+            report: None,
+            limits: ResourceLimits::default(),
+            idle_timeout: None,
+            watch_files: vec![],
+            retries: 0,
+            retry_delay: 10,
+            retry_on_exit_codes: vec![],
+            on_timeout: TimeoutHook::default(),
+            envs: vec![],
+            clear_env: false,
+            workdir: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            lock: false,
+            wait_lock: false,
+            status_file: None,
+            status_interval: 10,
+            forward_signals: ForwardSignals::default(),
+            control_socket: None,
+            subreaper: false,
         }
     }
 
@@ -54,138 +202,3662 @@ impl Runner {
         self
     }
 
+    /// Set deadline when the program will be terminated
+    pub fn with_deadline(mut self, deadline: DateTime<Local>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set grace period in seconds between SIGTERM and SIGKILL
+    pub fn with_kill_after(mut self, t: u64) -> Self {
+        self.kill_after = Some(t);
+        self
+    }
+
+    /// Set file path for saving run report
+    pub fn with_report<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.report = Some(path.as_ref().into());
+        self
+    }
+
+    /// Set resource limits for the program
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set idle timeout in seconds
+    pub fn with_idle_timeout(mut self, t: u64) -> Self {
+        self.idle_timeout = Some(t);
+        self
+    }
+
+    /// Watch output file for idle timeout
+    pub fn with_watch_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.watch_files.push(path.as_ref().into());
+        self
+    }
+
+    /// Set number of retries when the program fails
+    pub fn with_retries(mut self, n: usize) -> Self {
+        self.retries = n;
+        self
+    }
+
+    /// Set delay in seconds before the first retry
+    pub fn with_retry_delay(mut self, t: u64) -> Self {
+        self.retry_delay = t;
+        self
+    }
+
+    /// Retry only when the program exits with `code`
+    pub fn with_retry_on_exit_code(mut self, code: i32) -> Self {
+        self.retry_on_exit_codes.push(code);
+        self
+    }
+
+    /// Set the hook to run shortly before timeout
+    pub fn with_timeout_hook(mut self, hook: TimeoutHook) -> Self {
+        self.on_timeout = hook;
+        self
+    }
+
+    /// Set environment variable for the program
+    pub fn with_env<K: AsRef<str>, V: AsRef<str>>(mut self, key: K, val: V) -> Self {
+        self.envs.push((key.as_ref().into(), val.as_ref().into()));
+        self
+    }
+
+    /// Clear inherited environment variables for the program
+    pub fn with_clear_env(mut self) -> Self {
+        self.clear_env = true;
+        self
+    }
+
+    /// Set working directory for the program
+    pub fn with_workdir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.workdir = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Read stdin of the program from file
+    pub fn with_stdin<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdin = Some(path.as_ref().into());
+        self
+    }
+
+    /// Redirect stdout of the program to file
+    pub fn with_stdout<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stdout = Some(path.as_ref().into());
+        self
+    }
+
+    /// Redirect stderr of the program to file
+    pub fn with_stderr<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.stderr = Some(path.as_ref().into());
+        self
+    }
+
+    /// Lock working directory to prevent concurrent runs. Wait for the lock
+    /// if `wait` is true, or refuse to run.
+    pub fn with_lock(mut self, wait: bool) -> Self {
+        self.lock = true;
+        self.wait_lock = wait;
+        self
+    }
+
+    /// Write run status to file every `interval` seconds
+    pub fn with_status_file<P: AsRef<Path>>(mut self, path: P, interval: u64) -> Self {
+        self.status_file = Some(path.as_ref().into());
+        self.status_interval = interval;
+        self
+    }
+
+    /// Relay `signals` received by the runner to the program. An empty list
+    /// disables relaying.
+    pub fn with_forward_signals(mut self, signals: Vec<libc::c_int>) -> Self {
+        self.forward_signals = ForwardSignals {
+            signals,
+            disabled: false,
+        };
+        self
+    }
+
+    /// Accept control commands on a Unix domain socket at `path`
+    pub fn with_control_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.control_socket = Some(path.as_ref().into());
+        self
+    }
+
+    /// Track and terminate descendants escaped from the session
+    pub fn with_subreaper(mut self) -> Self {
+        self.subreaper = true;
+        self
+    }
+
+    /// Return wall time limit for the program from now on.
+    fn time_limit(&self) -> Option<Duration> {
+        wall_time_limit(self.timeout, self.deadline)
+    }
+
+    /// Test if the deadline has passed.
+    fn deadline_passed(&self) -> bool {
+        self.deadline.map_or(false, |d| Local::now() >= d)
+    }
+
+    /// Return working directory of the program.
+    fn workdir(&self) -> Result<PathBuf> {
+        match &self.workdir {
+            Some(d) => Ok(d.clone()),
+            None => Ok(std::env::current_dir()?),
+        }
+    }
+
+    /// Return full path to `path` relative to working directory.
+    fn resolve(&self, path: &Path) -> PathBuf {
+        match &self.workdir {
+            Some(d) => d.join(path),
+            None => path.into(),
+        }
+    }
+
+    /// Return files to watch for idle timeout, including redirected stdout
+    /// and stderr.
+    fn idle_watch_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<_> = self.watch_files.iter().map(|p| self.resolve(p)).collect();
+        files.extend(self.stdout.iter().map(|p| self.resolve(p)));
+        files.extend(self.stderr.iter().map(|p| self.resolve(p)));
+        files
+    }
+
+    /// Test if the program should be run again after `attempt`.
+    fn should_retry(&self, attempt: &Attempt) -> bool {
+        if attempt.attempt > self.retries || attempt.termination != Termination::Normal {
+            return false;
+        }
+
+        match attempt.exit_code {
+            Some(0) => false,
+            Some(code) if !self.retry_on_exit_codes.is_empty() => {
+                self.retry_on_exit_codes.contains(&code)
+            }
+            // any failure, including crashes by signal
+            _ => self.retry_on_exit_codes.is_empty(),
+        }
+    }
+
+    /// Return delay before the next retry after `attempt`, with exponential
+    /// backoff.
+    fn backoff(&self, attempt: usize) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1) as u32);
+        Duration::from_secs(self.retry_delay.saturating_mul(factor))
+    }
+
     /// Spawn child process in a new session.
-    pub fn build_command(&self) -> std::process::Command {
-        let mut cmd = process::Command::new("setsid");
-        cmd.arg("-w").arg(&self.program).args(&self.rest);
-        cmd
+    pub fn build_command(&self) -> Result<std::process::Command> {
+        use std::fs::File;
+        use std::os::unix::process::CommandExt;
+
+        let mut cmd = process::Command::new(&self.program);
+        cmd.args(&self.rest);
+
+        if self.clear_env {
+            cmd.env_clear();
+        }
+        cmd.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        if let Some(d) = &self.workdir {
+            cmd.current_dir(d);
+        }
+
+        // Capture program output for idle timeout if not redirected. We do
+        // not touch stdout when watching output files, since output
+        // buffering will change if stdout is not a terminal.
+        let capture = self.idle_timeout.is_some() && self.watch_files.is_empty();
+        if let Some(f) = &self.stdin {
+            cmd.stdin(File::open(self.resolve(f))?);
+        }
+        if let Some(f) = &self.stdout {
+            cmd.stdout(File::create(self.resolve(f))?);
+        } else if capture {
+            cmd.stdout(process::Stdio::piped());
+        }
+        if let Some(f) = &self.stderr {
+            cmd.stderr(File::create(self.resolve(f))?);
+        } else if capture {
+            cmd.stderr(process::Stdio::piped());
+        }
+
+        // Create a new session in child process before exec, so the child
+        // pid is also the session id. Resource limits are applied at the
+        // same time.
+        let limits = self.limits.clone();
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::setsid() < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                limits.apply()
+            });
+        }
+
+        Ok(cmd)
+    }
+}
+
+/// Parse environment variable in KEY=VAL form.
+fn parse_env(s: &str) -> std::result::Result<(String, String), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(v)) if !k.is_empty() => Ok((k.into(), v.into())),
+        _ => Err(format!(
+            "invalid environment variable: {}, expect KEY=VAL",
+            s
+        )),
     }
 }
+
+/// Default grace period in seconds between SIGTERM and SIGKILL.
+pub const DEFAULT_KILL_AFTER: u64 = 30;
 // runner:1 ends here
 
-// tokio
+// time
 
-// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*tokio][tokio:1]]
-use tokio::prelude::*;
-use tokio_process::CommandExt;
-use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*time][time:1]]
+/// Parse a duration in seconds (`90`) or in human-friendly form (`45s`,
+/// `30m`, `2h30m`, `1d12h`). Return the number of seconds.
+pub fn parse_duration(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    if let Ok(n) = s.parse() {
+        return Ok(n);
+    }
+    if s.is_empty() {
+        return Err("empty duration".into());
+    }
 
-pub fn run(args: &Runner) -> Result<()> {
-    // show program status
-    let app_name = format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"),);
-    println!("{} starts at {}", app_name, timestamp_now());
-    dbg!(args);
+    let invalid = || format!("invalid duration: {}, expect a form like 2h30m", s);
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            'd' => 24 * 3600,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        let n: u64 = digits.parse().map_err(|_| invalid())?;
+        total = n
+            .checked_mul(unit)
+            .and_then(|n| total.checked_add(n))
+            .ok_or_else(|| format!("duration is too long: {}", s))?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
 
-    // Use the standard library's `Command` type to build a process and then
-    // execute it via the `CommandExt` trait.
-    let child = args.build_command().spawn_async().map_err(|e| {
-        error!("Error while constructing command, details:\n {}", e);
-        e
-    })?;
+    Ok(total)
+}
 
-    let session_id = child.id();
-    info!("Job session id: {}", session_id);
+/// Parse an absolute deadline in RFC 3339 format, `YYYY-MM-DD HH:MM[:SS]`,
+/// or `HH:MM[:SS]` for the next occurrence of the time in local time zone.
+pub fn parse_deadline(s: &str) -> std::result::Result<DateTime<Local>, String> {
+    use chrono::{NaiveDateTime, NaiveTime, TimeZone};
 
-    // Create an infinite stream of signal notifications. Each item received on
-    // this stream may represent multiple signals.
-    let sig_int = Signal::new(SIGINT).flatten_stream().map_err(|e| ());
-    let sig_term = Signal::new(SIGTERM).flatten_stream().map_err(|e| ());
+    let s = s.trim();
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Local));
+    }
+
+    let local = |t: NaiveDateTime| {
+        Local
+            .from_local_datetime(&t)
+            .earliest()
+            .ok_or_else(|| format!("invalid local time: {}", s))
+    };
+    for fmt in &[
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(t) = NaiveDateTime::parse_from_str(s, fmt) {
+            return local(t);
+        }
+    }
+    for fmt in &["%H:%M:%S", "%H:%M"] {
+        if let Ok(t) = NaiveTime::parse_from_str(s, fmt) {
+            let now = Local::now().naive_local();
+            let mut day = now.date();
+            if now.time() >= t {
+                day = day.succ();
+            }
+            return local(day.and_time(t));
+        }
+    }
 
-    // When timeout, send TERM signal. Default timeout = 30 days
-    let t = args.timeout.unwrap_or(3600 * 24 * 30);
-    let timeout = Duration::from_secs(t);
+    Err(format!(
+        "invalid deadline: {}, expect a form like \"2019-07-26 08:00\" or \"08:00\"",
+        s
+    ))
+}
 
-    // Use the `select` combinator to merge these streams into one. Process
-    // signal as it comes in.
-    let signals = sig_int
-        .select(sig_term)
-        .timeout(timeout)
-        .take(1)
-        .for_each(move |_| {
-            println!("User interrupted.");
-            println!("Kill running processes ({}) ... ", session_id);
-            terminate_session(session_id).unwrap();
+/// Return wall time limit from now on, with `timeout` in seconds and an
+/// absolute `deadline`, whichever comes first.
+pub fn wall_time_limit(
+    timeout: Option<u64>,
+    deadline: Option<DateTime<Local>>,
+) -> Option<Duration> {
+    let timeout = timeout.map(Duration::from_secs);
+    let remaining = deadline.map(|d| (d - Local::now()).to_std().unwrap_or_default());
+    match (timeout, remaining) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DurationRepr {
+    Seconds(u64),
+    Text(String),
+}
+
+/// Deserialize a duration from the number of seconds or a human-friendly
+/// string such as "2h30m".
+pub fn deserialize_duration<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<DurationRepr>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(DurationRepr::Seconds(n)) => Ok(Some(n)),
+        Some(DurationRepr::Text(s)) => parse_duration(&s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+/// Deserialize a deadline in the forms accepted by `parse_deadline`.
+pub fn deserialize_deadline<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<DateTime<Local>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(s) => parse_deadline(&s)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+// time:1 ends here
+
+// limits
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*limits][limits:1]]
+/// Resource limits applied to the program using setrlimit.
+#[derive(StructOpt, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Maximum size of virtual memory in megabytes
+    #[structopt(
+        long = "max-memory",
+        value_name = "MB",
+        parse(try_from_str = "parse_megabytes")
+    )]
+    #[serde(default, deserialize_with = "deserialize_megabytes")]
+    pub max_memory: Option<u64>,
+
+    /// Maximum CPU time in seconds
+    #[structopt(long = "max-cpu-time", value_name = "SECONDS")]
+    pub max_cpu_time: Option<u64>,
+
+    /// Maximum size of files created by the program in megabytes
+    #[structopt(
+        long = "max-file-size",
+        value_name = "MB",
+        parse(try_from_str = "parse_megabytes")
+    )]
+    #[serde(default, deserialize_with = "deserialize_megabytes")]
+    pub max_file_size: Option<u64>,
+
+    /// Maximum number of open files
+    #[structopt(long = "max-open-files", value_name = "N")]
+    pub max_open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Test if no limit is set.
+    pub fn is_empty(&self) -> bool {
+        self.max_memory.is_none()
+            && self.max_cpu_time.is_none()
+            && self.max_file_size.is_none()
+            && self.max_open_files.is_none()
+    }
+
+    /// Apply limits to current process. This will be called in the child
+    /// process before exec, so no memory allocation here.
+    fn apply(&self) -> std::io::Result<()> {
+        let set = |resource, value: u64| {
+            let rlim = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: value as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(resource, &rlim) } == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        };
+
+        // io::Error::new allocates, so report overflow with a raw os error
+        let bytes = |m: u64| {
+            m.checked_mul(MEGABYTE)
+                .ok_or_else(|| std::io::Error::from_raw_os_error(libc::EINVAL))
+        };
+        if let Some(m) = self.max_memory {
+            set(libc::RLIMIT_AS, bytes(m)?)?;
+        }
+        if let Some(t) = self.max_cpu_time {
+            set(libc::RLIMIT_CPU, t)?;
+        }
+        if let Some(m) = self.max_file_size {
+            set(libc::RLIMIT_FSIZE, bytes(m)?)?;
+        }
+        if let Some(n) = self.max_open_files {
+            set(libc::RLIMIT_NOFILE, n)?;
+        }
+
+        Ok(())
+    }
+
+    /// Guess if the program was killed for exceeding resource limits.
+    fn exceeded_by(&self, status: &ExitStatus, usage: &ResourceUsage) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+
+        match status.signal() {
+            // soft limits on CPU time and file size
+            Some(libc::SIGXCPU) => self.max_cpu_time.is_some(),
+            Some(libc::SIGXFSZ) => self.max_file_size.is_some(),
+            // hard limit on CPU time
+            Some(libc::SIGKILL) => {
+                let cpu_time = usage.user_time + usage.sys_time;
+                self.max_cpu_time.map_or(false, |t| cpu_time >= t as f64)
+            }
+            // most programs crash or abort when an allocation fails under the
+            // limit on virtual memory, which is only told apart from other
+            // crashes by virtual memory size sampled close to the limit
+            Some(libc::SIGSEGV) | Some(libc::SIGABRT) | Some(libc::SIGBUS) => self
+                .max_memory
+                .map_or(false, |m| usage.peak_vm as f64 >= m as f64 * 1024.0 * 0.9),
+            _ => false,
+        }
+    }
+}
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+/// Parse a size in megabytes, which should fit in bytes.
+fn parse_megabytes(s: &str) -> std::result::Result<u64, String> {
+    let m: u64 = s
+        .trim()
+        .parse()
+        .map_err(|e| format!("invalid size {:?}: {}", s, e))?;
+    check_megabytes(m)
+}
+
+fn check_megabytes(m: u64) -> std::result::Result<u64, String> {
+    match m.checked_mul(MEGABYTE) {
+        Some(_) => Ok(m),
+        None => Err(format!("size is too large: {} MB", m)),
+    }
+}
+
+/// Deserialize a size in megabytes, which should fit in bytes.
+fn deserialize_megabytes<'de, D>(deserializer: D) -> std::result::Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match Option::<u64>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(m) => check_megabytes(m)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+// limits:1 ends here
+
+// hook
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*hook][hook:1]]
+/// Action for the program to make a checkpoint before killed on timeout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookAction {
+    /// Send a signal to processes in the session, such as SIGUSR1.
+    Signal(i32),
+    /// Create a file in working directory, such as STOPCAR for VASP.
+    File(PathBuf),
+    /// Run a shell command in working directory.
+    Command(String),
+}
+
+impl std::str::FromStr for HookAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("signal"), Some(name)) => parse_signal(name)
+                .map(HookAction::Signal)
+                .ok_or_else(|| format!("invalid signal: {}", name)),
+            (Some("file"), Some(path)) => Ok(HookAction::File(path.into())),
+            (Some("command"), Some(cmd)) => Ok(HookAction::Command(cmd.into())),
+            _ => Err(format!(
+                "invalid action: {}. Expect signal:NAME, file:PATH or command:CMD",
+                s
+            )),
+        }
+    }
+}
+
+impl HookAction {
+    /// Take the action for session `sid` in working directory `wdir`.
+    fn apply(&self, sid: u32, wdir: &Path) -> Result<()> {
+        match self {
+            HookAction::Signal(signal) => {
+                let pids = signal_processes_by_session_id(sid, *signal)?;
+                info!("Sent signal {} to processes: {:?}", signal, pids);
+            }
+            HookAction::File(path) => {
+                let path = wdir.join(path);
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)?;
+                info!("Created file: {}", path.display());
+            }
+            HookAction::Command(cmd) => {
+                let mut child = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(cmd)
+                    .current_dir(wdir)
+                    .env("RUNNER_SESSION_ID", sid.to_string())
+                    .spawn()?;
+                // wait in background, so we will not block the event loop
+                std::thread::spawn(move || match child.wait() {
+                    Ok(status) => info!("Hook command exited with {:?}", status),
+                    Err(e) => error!("Failed to wait for hook command: {}", e),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A hook taking action shortly before the program is killed on timeout, so
+/// the program could make a checkpoint.
+#[derive(StructOpt, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutHook {
+    /// Action to take before killing the program on timeout: signal:NAME,
+    /// file:PATH or command:CMD
+    #[structopt(long = "on-timeout", value_name = "ACTION")]
+    pub action: Option<HookAction>,
+
+    /// Take the on-timeout action SECONDS before timeout
+    #[structopt(
+        long = "on-timeout-before",
+        value_name = "SECONDS",
+        default_value = "60",
+        parse(try_from_str = "parse_duration")
+    )]
+    pub before: u64,
+}
+
+impl Default for TimeoutHook {
+    fn default() -> Self {
+        Self {
+            action: None,
+            before: 60,
+        }
+    }
+}
+
+impl TimeoutHook {
+    /// Return a future taking the hook action for session `sid` in working
+    /// directory `wdir`, `self.before` seconds ahead of the current
+    /// `deadline`, which could be extended in the meantime.
+    pub fn schedule<D>(
+        &self,
+        sid: u32,
+        deadline: D,
+        wdir: PathBuf,
+    ) -> impl Future<Item = (), Error = ()> + Send
+    where
+        D: Fn() -> Option<Instant> + Send + Sync + 'static,
+    {
+        let action = self.action.clone();
+        let before = Duration::from_secs(self.before);
+        if deadline().map_or(false, |d| d <= Instant::now() + before) {
+            warn!("on-timeout action will be taken immediately.");
+        }
+        let due = move || deadline().map(|d| d.checked_sub(before).unwrap_or_else(Instant::now));
+        let due = Arc::new(due);
+
+        future::loop_fn((), move |_| {
+            let due = due.clone();
+            let at = due().unwrap_or_else(Instant::now);
+            tokio::timer::Delay::new(at).map(move |_| match due() {
+                Some(t) if t > Instant::now() => future::Loop::Continue(()),
+                Some(_) => future::Loop::Break(true),
+                None => future::Loop::Break(false),
+            })
+        })
+        .map_err(|e| error!("timeout hook timer error: {}", e))
+        .and_then(move |due| {
+            // the timeout could have been removed
+            if !due {
+                return Ok(());
+            }
+            // the session could have gone
+            match alive_session_members(sid) {
+                Ok(ref pids) if !pids.is_empty() => {}
+                _ => return Ok(()),
+            }
+            if let Some(action) = action {
+                info!("Take on-timeout action: {:?}", action);
+                if let Err(e) = action.apply(sid, &wdir) {
+                    error!("Failed to take on-timeout action: {}", e);
+                }
+            }
             Ok(())
         })
-        .map_err(move |_| {
-            error!("Command timeout after {} seconds!", t);
-            terminate_session(session_id).unwrap();
-        });
+    }
+}
+// hook:1 ends here
 
-    let cmd = child
-        .map(|status| println!("exit status: {:?}", status))
-        .map_err(|e| error!("cmd failed with errors\n: {}", e));
+// lock
 
-    tokio::run(signals.select2(cmd).map(|_| ()).map_err(|_| ()));
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*lock][lock:1]]
+/// Name of lock file in working directory.
+pub const LOCK_FILE: &str = ".runner.lock";
 
-    Ok(())
+/// Information recorded in the lock file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LockInfo {
+    /// Process id of the runner
+    pid: u32,
+    /// Session id of the running program
+    session: Option<u32>,
 }
-// tokio:1 ends here
 
-// utils
+/// An advisory lock on a file preventing concurrent runs in the same
+/// directory. The lock is released when dropped.
+#[derive(Debug)]
+pub struct RunLock {
+    file: std::fs::File,
+    path: PathBuf,
+}
 
-// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*utils][utils:1]]
-use duct::cmd;
+impl RunLock {
+    /// Acquire lock on `path`. Wait until the lock is released if `wait` is
+    /// true, or fail immediately.
+    pub fn acquire<P: AsRef<Path>>(path: P, wait: bool) -> Result<Self> {
+        let path = path.as_ref();
 
-/// kill child processes based on pstree cmd, which is not very reliable.
-fn kill_child_processes() -> Result<()> {
-    let pid = format!("{}", process::id());
+        let mut waiting = false;
+        loop {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(path)?;
 
-    // hide threads using -T option
-    let output = cmd!("pstree", "-plT", &pid)
-        .pipe(cmd!("grep", "([[:digit:]]*)", "-o"))
-        .pipe(cmd!("tr", "-d", "()"))
-        .read()?;
+            if try_flock(&file)? {
+                let mut lock = Self {
+                    file,
+                    path: path.into(),
+                };
+                lock.update(None)?;
+                return Ok(lock);
+            }
+
+            // The lock is held by others. Never remove the lock file here:
+            // someone holds the lock on it, and removing it would let another
+            // runner lock a new file in the same directory. If the recorded
+            // runner has gone, its information is merely stale.
+            let holder = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|s| serde_json::from_str::<LockInfo>(&s).ok());
+            let holder = match holder {
+                Some(ref info) if !process_alive(info.pid) => format!(
+                    "{:?} (stale: runner {} has gone, the lock could be held by its leftovers)",
+                    info, info.pid
+                ),
+                _ => format!("{:?}", holder),
+            };
+            if !wait {
+                bail!("{} is locked by another runner: {}", path.display(), holder);
+            }
+            if !waiting {
+                info!("Waiting for lock {} held by {} ...", path.display(), holder);
+                waiting = true;
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
 
-    let mut sub_pids: std::collections::HashSet<_> = output.split_whitespace().collect();
+    /// Record runner pid and program session id `sid` in the lock file.
+    pub fn update(&mut self, sid: Option<u32>) -> Result<()> {
+        use std::io::{Seek, SeekFrom};
 
-    // remove main process id from process list
-    sub_pids.remove(pid.as_str());
+        let info = LockInfo {
+            pid: process::id(),
+            session: sid,
+        };
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        serde_json::to_writer(&mut self.file, &info)?;
+        self.file.flush()?;
 
-    if !sub_pids.is_empty() {
-        cmd("kill", &sub_pids)
-            .unchecked()
-            .then(cmd!("pstree", "-pagTl", &pid))
-            .run()?;
+        Ok(())
     }
+}
 
-    Ok(())
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Keep the lock file, since others could be waiting for it. The lock
+        // will be released on closing.
+        let _ = self.file.set_len(0);
+        debug!("lock released: {}", self.path.display());
+    }
 }
 
-/// terminate child processes in a session.
-pub fn terminate_session(sid: u32) -> Result<()> {
-    signal_processes_by_session_id(sid, "SIGTERM")
+/// Try to put an exclusive flock on `file` without blocking.
+fn try_flock(file: &std::fs::File) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if rc == 0 {
+        return Ok(true);
+    }
+
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(e.into())
+    }
 }
 
-/// Kill processes in a session.
-pub fn kill_session(sid: u32) -> Result<()> {
-    signal_processes_by_session_id(sid, "SIGKILL")
+/// Test if process `pid` is still alive.
+fn process_alive(pid: u32) -> bool {
+    let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
 }
+// lock:1 ends here
 
-/// Resume processes in a session.
-pub fn resume_session(sid: u32) -> Result<()> {
-    signal_processes_by_session_id(sid, "SIGCONT")
+// report
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*report][report:1]]
+/// How a run came to an end.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Termination {
+    /// The program exited by itself.
+    Normal,
+    /// The program was terminated when timeout.
+    Timeout,
+    /// The program was terminated on user interruption.
+    Interrupted,
+    /// The program was killed by resource limit.
+    ResourceLimit,
+    /// The program was terminated when no output within idle timeout.
+    Stalled,
 }
 
-/// Pause processes in a session.
-pub fn pause_session(sid: u32) -> Result<()> {
-    signal_processes_by_session_id(sid, "SIGSTOP")
+/// Summary of a finished run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    /// Timestamp when the program starts
+    pub start_time: String,
+    /// Timestamp when the program ends
+    pub end_time: String,
+    /// Wall time in seconds
+    pub wall_time: f64,
+    /// Exit code of the program, if it exited normally
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the program
+    pub signal: Option<i32>,
+    /// How the run ends
+    pub termination: Termination,
+    /// Resource usage of the session
+    pub usage: ResourceUsage,
+    /// Outcome of each attempt to run the program
+    pub attempts: Vec<Attempt>,
 }
 
-/// signal processes by session id
-fn signal_processes_by_session_id(sid: u32, signal: &str) -> Result<()> {
-    // cmdline: kill -CONT -- $(ps -s $1 -o pid=)
-    let output = cmd!("ps", "-s", format!("{}", sid), "-o", "pid=").read()?;
-    let pids: Vec<_> = output.split_whitespace().collect();
+/// Outcome of a single attempt to run the program.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attempt {
+    /// Attempt number, starting from 1
+    pub attempt: usize,
+    /// Timestamp when the attempt starts
+    pub start_time: String,
+    /// Timestamp when the attempt ends
+    pub end_time: String,
+    /// Wall time in seconds
+    pub wall_time: f64,
+    /// Exit code of the program, if it exited normally
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the program
+    pub signal: Option<i32>,
+    /// How the attempt ends
+    pub termination: Termination,
+    /// Resource usage of the session
+    pub usage: ResourceUsage,
+}
 
-    let mut args = vec!["-s", signal, "--"];
-    args.extend(&pids);
-    if !pids.is_empty() {
-        cmd("kill", &args).unchecked().run()?;
-    } else {
-        info!("No remaining processes found!");
+impl RunReport {
+    /// Summarize all attempts. The outcome of the run is the outcome of the
+    /// final attempt.
+    fn new(start_time: String, wall_time: f64, attempts: Vec<Attempt>) -> Self {
+        let last = attempts.last().expect("no attempt").clone();
+        Self {
+            start_time,
+            end_time: last.end_time,
+            wall_time,
+            exit_code: last.exit_code,
+            signal: last.signal,
+            termination: last.termination,
+            usage: last.usage,
+            attempts,
+        }
     }
 
-    Ok(())
+    /// Return exit code for the runner: the exit code of the program, or
+    /// 128+signal if the program was killed by a signal. Exit status of an
+    /// attached program is unknown, so it is 0 if the program ends normally.
+    pub fn code(&self) -> i32 {
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => code,
+            (None, Some(sig)) => 128 + sig,
+            (None, None) if self.termination == Termination::Normal => 0,
+            (None, None) => 1,
+        }
+    }
+
+    /// Save report in JSON format to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
 }
-// utils:1 ends here
+// report:1 ends here
+
+// status
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*status][status:1]]
+/// State of a run in the status file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunState {
+    /// The program is running.
+    Running,
+    /// The program is stopped, for example by SIGSTOP.
+    Stopped,
+    /// Waiting to retry after a failed attempt.
+    Waiting,
+    /// The run has finished.
+    Finished,
+}
+
+/// Status of a run, written to the status file periodically.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunStatus {
+    /// Process id of the runner
+    pub pid: u32,
+    /// Session id of the program
+    pub session: Option<u32>,
+    /// Timestamp when the run starts
+    pub start_time: String,
+    /// Timestamp of this update
+    pub update_time: String,
+    /// Elapsed wall time in seconds
+    pub elapsed: f64,
+    /// Remaining time in seconds before timeout
+    pub remaining: Option<f64>,
+    /// CPU time in seconds of processes in the session
+    pub cpu_time: f64,
+    /// CPU usage in percent since last update
+    pub cpu_usage: f64,
+    /// State of the run
+    pub state: RunState,
+    /// Outcome of the run, only available when finished
+    pub report: Option<RunReport>,
+}
+
+/// Write status of a run to a file periodically, so external monitors could
+/// tell if the runner is alive from staleness of the file.
+#[derive(Debug)]
+struct Heartbeat {
+    path: PathBuf,
+    start_time: String,
+    start: Instant,
+    /// session id, CPU time and the time at last update
+    last_cpu: Option<(u32, f64, Instant)>,
+    /// no more updates after the final status written
+    finished: bool,
+}
+
+impl Heartbeat {
+    fn new<P: AsRef<Path>>(path: P, start_time: &str) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            start_time: start_time.into(),
+            start: Instant::now(),
+            last_cpu: None,
+            finished: false,
+        }
+    }
+
+    /// Update status of program running in session `sid`, which will be
+    /// terminated at `deadline`. No session means waiting for the next
+    /// attempt.
+    fn beat(&mut self, sid: Option<u32>, deadline: Option<Instant>) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        let sid = match sid {
+            Some(sid) => sid,
+            None => return self.wait(),
+        };
+
+        let now = Instant::now();
+        let cpu_time = session_cpu_time(sid)?;
+        let cpu_usage = match self.last_cpu {
+            Some((last_sid, last_cpu, last_time)) if last_sid == sid => {
+                let dt = now.duration_since(last_time).as_secs_f64();
+                // CPU time could drop when processes exit
+                if dt > 0.0 {
+                    ((cpu_time - last_cpu) / dt * 100.0).max(0.0)
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+        self.last_cpu = Some((sid, cpu_time, now));
+
+        let state = match ProcessStat::from_pid(sid) {
+            Some(ref p) if p.state == 'T' => RunState::Stopped,
+            _ => RunState::Running,
+        };
+        let remaining = deadline.map(|d| {
+            d.checked_duration_since(now)
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+
+        self.write(&RunStatus {
+            pid: process::id(),
+            session: Some(sid),
+            start_time: self.start_time.clone(),
+            update_time: timestamp_now(),
+            elapsed: self.start.elapsed().as_secs_f64(),
+            remaining,
+            cpu_time,
+            cpu_usage,
+            state,
+            report: None,
+        })
+    }
+
+    /// Update status between attempts.
+    fn wait(&mut self) -> Result<()> {
+        self.write(&RunStatus {
+            pid: process::id(),
+            session: None,
+            start_time: self.start_time.clone(),
+            update_time: timestamp_now(),
+            elapsed: self.start.elapsed().as_secs_f64(),
+            remaining: None,
+            cpu_time: 0.0,
+            cpu_usage: 0.0,
+            state: RunState::Waiting,
+            report: None,
+        })
+    }
+
+    /// Write the final status with outcome of the run.
+    fn finish(&mut self, report: &RunReport) -> Result<()> {
+        self.finished = true;
+        self.write(&RunStatus {
+            pid: process::id(),
+            session: self.last_cpu.map(|(sid, _, _)| sid),
+            start_time: self.start_time.clone(),
+            update_time: timestamp_now(),
+            elapsed: self.start.elapsed().as_secs_f64(),
+            remaining: None,
+            cpu_time: report.usage.user_time + report.usage.sys_time,
+            cpu_usage: 0.0,
+            state: RunState::Finished,
+            report: Some(report.clone()),
+        })
+    }
+
+    /// Replace the status file atomically, so readers never see a partial
+    /// file.
+    fn write(&self, status: &RunStatus) -> Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(status)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Return total CPU time in seconds of processes in session `sid`.
+fn session_cpu_time(sid: u32) -> Result<f64> {
+    let ticks: u64 = all_processes()?
+        .iter()
+        .filter(|p| p.session == sid)
+        .map(|p| p.utime + p.stime)
+        .sum();
+    let clk_tck = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+
+    Ok(ticks as f64 / clk_tck)
+}
+// status:1 ends here
+
+// tokio
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*tokio][tokio:1]]
+use tokio::prelude::*;
+use tokio::timer::{Delay, Interval};
+use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+/// Shared state for supervising a running session.
+#[derive(Debug, Clone)]
+struct Supervisor {
+    sid: u32,
+    grace: Duration,
+    termination: Arc<Mutex<Termination>>,
+    /// descendants tracked in subreaper mode
+    descendants: Option<Descendants>,
+    /// completion of escalations to SIGKILL in progress
+    escalations: Arc<Mutex<Vec<oneshot::Receiver<()>>>>,
+}
+
+impl Supervisor {
+    fn new(sid: u32, grace: Duration) -> Self {
+        Self {
+            sid,
+            grace,
+            termination: Arc::new(Mutex::new(Termination::Normal)),
+            descendants: None,
+            escalations: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Record `reason` for termination. Return false if terminated already.
+    fn set_termination(&self, reason: Termination) -> bool {
+        let mut termination = self.termination.lock().unwrap();
+        if *termination != Termination::Normal {
+            return false;
+        }
+        *termination = reason;
+        true
+    }
+
+    /// Terminate the session for `reason`. Only the first reason will be
+    /// recorded.
+    fn terminate(&self, reason: Termination) {
+        if !self.set_termination(reason) {
+            return;
+        }
+
+        println!("Kill running processes ({}) ... ", self.sid);
+        let (sid, grace) = (self.sid, self.grace);
+        self.escalate(move || {
+            if let Err(e) = terminate_session_gracefully(sid, grace) {
+                error!("Failed to terminate session {}: {}", sid, e);
+            }
+        });
+        if let Some(descendants) = self.descendants.clone() {
+            self.escalate(move || {
+                if let Err(e) = descendants.terminate_escaped(grace) {
+                    error!("Failed to terminate escaped processes: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Run `escalation` in background, so we will not block the event loop.
+    fn escalate<F: FnOnce() + Send + 'static>(&self, escalation: F) {
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            escalation();
+            let _ = tx.send(());
+        });
+        self.escalations.lock().unwrap().push(rx);
+    }
+
+    /// Return a future resolved when escalations started so far are done, so
+    /// that processes surviving SIGTERM will not be left running.
+    fn escalated(&self) -> impl Future<Item = (), Error = ()> + Send {
+        let pending: Vec<_> = self.escalations.lock().unwrap().drain(..).collect();
+        future::join_all(pending).then(|_| Ok(()))
+    }
+
+    /// Kill the session immediately for `reason`.
+    fn kill(&self, reason: Termination) -> Result<Vec<u32>> {
+        self.set_termination(reason);
+        let mut killed = kill_session(self.sid)?;
+        if let Some(descendants) = &self.descendants {
+            killed.extend(descendants.kill_escaped()?);
+        }
+        Ok(killed)
+    }
+
+    /// Return how the session ends.
+    fn termination(&self) -> Termination {
+        *self.termination.lock().unwrap()
+    }
+}
+
+/// Signals relayed from the runner to processes of the program.
+#[derive(StructOpt, Debug, Clone)]
+pub struct ForwardSignals {
+    /// Signals relayed to every process of the program (comma separated)
+    #[structopt(
+        long = "forward-signals",
+        value_name = "SIGNALS",
+        default_value = "USR1,USR2,HUP",
+        raw(use_delimiter = "true", require_delimiter = "true"),
+        parse(try_from_str = "parse_signal_arg")
+    )]
+    signals: Vec<libc::c_int>,
+
+    /// Do not relay any signal to the program
+    #[structopt(long = "no-forward-signals")]
+    disabled: bool,
+}
+
+impl Default for ForwardSignals {
+    fn default() -> Self {
+        Self {
+            signals: vec![libc::SIGUSR1, libc::SIGUSR2, libc::SIGHUP],
+            disabled: false,
+        }
+    }
+}
+
+impl ForwardSignals {
+    /// Relay signals to every process in the session returned by `session`,
+    /// which could change between attempts.
+    fn relay<F>(&self, session: F) -> impl Future<Item = (), Error = ()> + Send
+    where
+        F: Fn() -> Option<u32> + Clone + Send + 'static,
+    {
+        let signals = if self.disabled {
+            &[][..]
+        } else {
+            &self.signals[..]
+        };
+        let relays: Vec<_> = signals
+            .iter()
+            .map(|&sig| {
+                let session = session.clone();
+                Signal::new(sig)
+                    .flatten_stream()
+                    .map_err(move |e| error!("signal {} error: {}", sig, e))
+                    .for_each(move |_| {
+                        match session() {
+                            Some(sid) => match signal_processes_by_session_id(sid, sig) {
+                                Ok(pids) => {
+                                    info!("Forward signal {} to session {}: {:?}", sig, sid, pids)
+                                }
+                                Err(e) => error!("Failed to forward signal {}: {}", sig, e),
+                            },
+                            None => warn!("Signal {} ignored: no program running.", sig),
+                        }
+                        Ok(())
+                    })
+            })
+            .collect();
+
+        future::join_all(relays).map(|_| ())
+    }
+}
+
+/// Time of the latest output activity of the program.
+#[derive(Debug, Clone)]
+struct Activity(Arc<Mutex<Instant>>);
+
+impl Activity {
+    fn new() -> Self {
+        Activity(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    /// Return the time elapsed since the latest activity.
+    fn idle(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+
+    /// Relay program output from `src` to `dst` in background, recording
+    /// activity on the way.
+    fn relay<R, W>(&self, mut src: R, mut dst: W)
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let activity = self.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 8192];
+            loop {
+                match src.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        activity.touch();
+                        let _ = dst.write_all(&buf[..n]);
+                        let _ = dst.flush();
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    /// Record activity if any of `files` has been modified since last check.
+    fn check_files(&self, files: &[PathBuf], mtimes: &mut Vec<Option<std::time::SystemTime>>) {
+        mtimes.resize(files.len(), None);
+        for (file, last) in files.iter().zip(mtimes.iter_mut()) {
+            let mtime = std::fs::metadata(file).and_then(|m| m.modified()).ok();
+            if mtime != *last {
+                *last = mtime;
+                self.touch();
+            }
+        }
+    }
+}
+
+/// Control over a run in background. The session of the program changes
+/// between attempts.
+#[derive(Debug, Clone, Default)]
+pub struct RunController {
+    inner: Arc<Mutex<Control>>,
+}
+
+#[derive(Debug, Default)]
+struct Control {
+    /// supervisor of the running attempt
+    supervisor: Option<Supervisor>,
+    /// number of the running or latest attempt
+    attempt: usize,
+    /// when the running attempt will be timed out
+    deadline: Option<Instant>,
+    /// no more attempts when stopped
+    stopped: bool,
+    /// waiting to retry after a failed attempt
+    waiting: bool,
+    /// wake up from retry delay when stopped
+    wakeup: Option<oneshot::Sender<()>>,
+}
+
+impl RunController {
+    /// Return process id of the running program, which is also its session
+    /// id.
+    pub fn pid(&self) -> Option<u32> {
+        self.inner
+            .lock()
+            .unwrap()
+            .supervisor
+            .as_ref()
+            .map(|s| s.sid)
+    }
+
+    /// Pause processes of the running program.
+    pub fn pause(&self) -> Result<Vec<u32>> {
+        match self.pid() {
+            Some(sid) => pause_session(sid),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Resume processes of the running program.
+    pub fn resume(&self) -> Result<Vec<u32>> {
+        match self.pid() {
+            Some(sid) => resume_session(sid),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Terminate the running program gracefully, and cancel further attempts.
+    pub fn terminate(&self) {
+        if let Some(supervisor) = self.stop() {
+            supervisor.terminate(Termination::Interrupted);
+        }
+    }
+
+    /// Kill the running program immediately, and cancel further attempts.
+    pub fn kill(&self) -> Result<Vec<u32>> {
+        match self.stop() {
+            Some(supervisor) => supervisor.kill(Termination::Interrupted),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Cancel further attempts. Return supervisor of the running attempt.
+    fn stop(&self) -> Option<Supervisor> {
+        let mut control = self.inner.lock().unwrap();
+        control.stopped = true;
+        if let Some(tx) = control.wakeup.take() {
+            let _ = tx.send(());
+        }
+        control.supervisor.clone()
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.inner.lock().unwrap().stopped
+    }
+
+    /// Postpone the timeout of the running attempt by `t`. Return the time
+    /// remaining, or None if no timeout set.
+    pub fn extend_timeout(&self, t: Duration) -> Option<Duration> {
+        let mut control = self.inner.lock().unwrap();
+        let deadline = control.deadline.as_mut()?;
+        *deadline += t;
+        Some(
+            deadline
+                .checked_duration_since(Instant::now())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Return current status of the run.
+    pub fn status(&self) -> ControlStatus {
+        let control = self.inner.lock().unwrap();
+        let session = control.supervisor.as_ref().map(|s| s.sid);
+        let state = match session.and_then(ProcessStat::from_pid) {
+            Some(ref p) if p.state == 'T' => RunState::Stopped,
+            _ if control.stopped && session.is_none() => RunState::Finished,
+            _ if control.waiting => RunState::Waiting,
+            _ => RunState::Running,
+        };
+        let remaining = control.deadline.map(|d| {
+            d.checked_duration_since(Instant::now())
+                .unwrap_or_default()
+                .as_secs_f64()
+        });
+
+        ControlStatus {
+            pid: process::id(),
+            session,
+            attempt: control.attempt,
+            state,
+            remaining,
+        }
+    }
+
+    /// Return when the running attempt will be timed out.
+    fn deadline(&self) -> Option<Instant> {
+        self.inner.lock().unwrap().deadline
+    }
+
+    /// Record the running attempt, which will be timed out after `limit`.
+    fn start_attempt(&self, attempt: usize, supervisor: Supervisor, limit: Option<Duration>) {
+        let mut control = self.inner.lock().unwrap();
+        control.attempt = attempt;
+        control.waiting = false;
+        control.supervisor = Some(supervisor);
+        control.deadline = limit.map(|t| Instant::now() + t);
+    }
+
+    fn finish_attempt(&self) {
+        let mut control = self.inner.lock().unwrap();
+        control.supervisor = None;
+        control.deadline = None;
+    }
+
+    /// Sleep for `delay` before the next attempt. Resolve to true if stopped
+    /// in the meantime.
+    fn sleep(&self, delay: Duration) -> impl Future<Item = bool, Error = Error> + Send {
+        let (tx, rx) = oneshot::channel();
+        let stopped = {
+            let mut control = self.inner.lock().unwrap();
+            control.wakeup = Some(tx);
+            control.waiting = true;
+            control.stopped
+        };
+
+        let at = if stopped {
+            Instant::now()
+        } else {
+            Instant::now() + delay
+        };
+        let ctl = self.clone();
+        Delay::new(at)
+            .select2(rx)
+            .then(move |_| Ok(ctl.is_stopped()))
+    }
+}
+
+type AttemptFuture = Box<dyn Future<Item = Attempt, Error = Error> + Send>;
+type AttemptLoop = future::Loop<Vec<Attempt>, (Vec<Attempt>, usize, Option<AttemptFuture>)>;
+
+/// Handle to a run in background. As a future, it resolves to the report of
+/// the run.
+pub struct RunHandle {
+    controller: RunController,
+    report: Box<dyn Future<Item = RunReport, Error = Error> + Send>,
+}
+
+impl RunHandle {
+    /// Return a cloneable controller of the run.
+    pub fn controller(&self) -> RunController {
+        self.controller.clone()
+    }
+
+    /// Return process id of the running program, which is also its session
+    /// id.
+    pub fn pid(&self) -> Option<u32> {
+        self.controller.pid()
+    }
+
+    /// Pause processes of the running program.
+    pub fn pause(&self) -> Result<Vec<u32>> {
+        self.controller.pause()
+    }
+
+    /// Resume processes of the running program.
+    pub fn resume(&self) -> Result<Vec<u32>> {
+        self.controller.resume()
+    }
+
+    /// Terminate the running program gracefully, and cancel further attempts.
+    pub fn terminate(&self) {
+        self.controller.terminate()
+    }
+
+    /// Kill the running program immediately, and cancel further attempts.
+    pub fn kill(&self) -> Result<Vec<u32>> {
+        self.controller.kill()
+    }
+}
+
+impl Future for RunHandle {
+    type Item = RunReport;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.report.poll()
+    }
+}
+
+impl Runner {
+    /// Spawn the program in background, and return a handle to control it.
+    /// This must be called within a tokio executor, on which supervising tasks
+    /// will be spawned.
+    pub fn spawn(&self) -> Result<RunHandle> {
+        let args = Arc::new(self.clone());
+        let start_time = timestamp_now();
+        let start = Instant::now();
+
+        if args.deadline_passed() {
+            bail!("Deadline {:?} has passed.", args.deadline);
+        }
+
+        // orphaned descendants will be reparented to us
+        if args.subreaper {
+            set_child_subreaper()?;
+        }
+
+        // prevent concurrent runs in the same working directory
+        let lock = if args.lock || args.wait_lock {
+            let path = args.workdir()?.join(LOCK_FILE);
+            let lock = RunLock::acquire(path, args.wait_lock)?;
+            Some(Arc::new(Mutex::new(lock)))
+        } else {
+            None
+        };
+
+        // report status periodically
+        let heartbeat = args
+            .status_file
+            .as_ref()
+            .map(|path| Arc::new(Mutex::new(Heartbeat::new(path, &start_time))));
+
+        let controller = RunController::default();
+        let socket = match &args.control_socket {
+            Some(path) => Some(ControlSocket::bind(path, controller.clone())?),
+            None => None,
+        };
+        let first = spawn_attempt(&args, 1, &controller, lock.clone())?;
+
+        // Update status file periodically for the whole run, including the
+        // delays between attempts.
+        let (beat_done, beat_stopped) = oneshot::channel::<()>();
+        if let Some(heartbeat) = heartbeat.clone() {
+            let (ctl, ctl_) = (controller.clone(), controller.clone());
+            let beat = keep_heartbeat(
+                heartbeat,
+                args.status_interval,
+                move || ctl.pid(),
+                move || ctl_.deadline(),
+            );
+            spawn_until(beat, &beat_stopped.shared());
+        }
+
+        // run the program again on failure if required
+        let (ctl, runner, lock_) = (controller.clone(), args.clone(), lock.clone());
+        let attempts = future::loop_fn(
+            (vec![], 1, Some(first)),
+            move |(mut attempts, i, pending)| -> Box<dyn Future<Item = AttemptLoop, Error = Error> + Send> {
+                let attempt = match pending {
+                    Some(attempt) => attempt,
+                    None => {
+                        match spawn_attempt(&runner, i, &ctl, lock_.clone()) {
+                            Ok(attempt) => attempt,
+                            Err(e) => return Box::new(future::err(e)),
+                        }
+                    }
+                };
+
+                let (ctl, runner) = (ctl.clone(), runner.clone());
+                let next = attempt.and_then(move |attempt| -> Box<dyn Future<Item = AttemptLoop, Error = Error> + Send> {
+                    let retry = !ctl.is_stopped() && runner.should_retry(&attempt);
+                    attempts.push(attempt);
+                    if !retry {
+                        return Box::new(future::ok(future::Loop::Break(attempts)));
+                    }
+
+                    let delay = runner.backoff(i);
+                    warn!("Attempt {} failed, retry in {} seconds ...", i, delay.as_secs());
+                    let next = ctl.sleep(delay).map(move |stopped| {
+                        if stopped {
+                            info!("Retry cancelled.");
+                            future::Loop::Break(attempts)
+                        } else if runner.deadline_passed() {
+                            warn!("Deadline {:?} has passed, stop retrying.", runner.deadline);
+                            future::Loop::Break(attempts)
+                        } else {
+                            future::Loop::Continue((attempts, i + 1, None))
+                        }
+                    });
+                    Box::new(next)
+                });
+                Box::new(next)
+            },
+        );
+
+        let report = attempts.and_then(move |attempts| {
+            let report = RunReport::new(start_time, start.elapsed().as_secs_f64(), attempts);
+            let _ = beat_done.send(());
+            if let Some(heartbeat) = &heartbeat {
+                heartbeat.lock().unwrap().finish(&report)?;
+            }
+            if let Some(path) = &args.report {
+                report.save(path)?;
+                info!("Run report wrote to: {}", path.display());
+            }
+            // release the lock and stop accepting commands when all done
+            drop(lock);
+            drop(socket);
+
+            Ok(report)
+        });
+
+        Ok(RunHandle {
+            controller,
+            report: Box::new(report),
+        })
+    }
+}
+
+/// Run the program until done, terminating it on user interruption.
+pub fn run(args: &Runner) -> Result<RunReport> {
+    // show program status
+    let app_name = format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"),);
+    println!("{} starts at {}", app_name, timestamp_now());
+    dbg!(args);
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    let runner = args.clone();
+    let handle = rt.block_on(future::lazy(move || runner.spawn()))?;
+
+    // Terminate the program on the first signal, and kill it on the next.
+    let (ctl, ctl_) = (handle.controller(), handle.controller());
+    rt.spawn(handle_interrupts(
+        move || ctl.terminate(),
+        move || ctl_.kill(),
+    ));
+
+    // Relay other signals to the program.
+    let ctl = handle.controller();
+    rt.spawn(args.forward_signals.relay(move || ctl.pid()));
+
+    let report = rt.block_on(handle);
+    let _ = rt.shutdown_now().wait();
+
+    report
+}
+
+/// Call `terminate` on the first SIGINT or SIGTERM, and `kill` on the next.
+fn handle_interrupts<T, K>(terminate: T, kill: K) -> impl Future<Item = (), Error = ()> + Send
+where
+    T: Fn() + Send + 'static,
+    K: Fn() -> Result<Vec<u32>> + Send + 'static,
+{
+    // Create an infinite stream of signal notifications. Each item received on
+    // this stream may represent multiple signals.
+    let sig_int = Signal::new(SIGINT).flatten_stream();
+    let sig_term = Signal::new(SIGTERM).flatten_stream();
+
+    let mut interrupted = false;
+    sig_int
+        .select(sig_term)
+        .map_err(|e| error!("signal error: {}", e))
+        .for_each(move |_| {
+            if interrupted {
+                println!("Kill running processes immediately ...");
+                if let Err(e) = kill() {
+                    error!("{}", e);
+                }
+            } else {
+                println!("User interrupted.");
+                terminate();
+                interrupted = true;
+            }
+            Ok(())
+        })
+}
+
+/// Update status of the current `session` through `heartbeat` every
+/// `interval` seconds, with remaining time to the current `deadline`.
+fn keep_heartbeat<S, D>(
+    heartbeat: Arc<Mutex<Heartbeat>>,
+    interval: u64,
+    session: S,
+    deadline: D,
+) -> impl Future<Item = (), Error = ()> + Send
+where
+    S: Fn() -> Option<u32> + Send + 'static,
+    D: Fn() -> Option<Instant> + Send + 'static,
+{
+    let interval = Duration::from_secs(interval.max(1));
+    Interval::new(Instant::now(), interval)
+        .map_err(|e| error!("status timer error: {}", e))
+        .for_each(move |_| {
+            if let Err(e) = heartbeat.lock().unwrap().beat(session(), deadline()) {
+                error!("Failed to update status file: {}", e);
+            }
+            Ok(())
+        })
+}
+
+/// Spawn `task` onto current executor, which will be stopped when `done`
+/// resolves.
+fn spawn_until<F>(task: F, done: &future::Shared<oneshot::Receiver<()>>)
+where
+    F: Future<Item = (), Error = ()> + Send + 'static,
+{
+    tokio::spawn(task.select2(done.clone()).then(|_| Ok(())));
+}
+
+/// Spawn the program once in a new session, with supervising tasks spawned
+/// onto current executor. Return a future resolving to the outcome.
+fn spawn_attempt(
+    args: &Arc<Runner>,
+    attempt: usize,
+    controller: &RunController,
+    lock: Option<Arc<Mutex<RunLock>>>,
+) -> Result<AttemptFuture> {
+    use std::os::unix::process::ExitStatusExt;
+
+    info!("Run program: attempt {}/{}", attempt, args.retries + 1);
+    let start_time = timestamp_now();
+    let start = Instant::now();
+
+    // Use the standard library's `Command` type to build a process and then
+    // watch it in background.
+    let mut cmd = args.build_command()?;
+    // mark descendants so that they can be recognized after reparenting
+    let token = track_token();
+    if args.subreaper {
+        cmd.env(TRACK_ENV, &token);
+    }
+    let mut child = SessionChild::spawn(&mut cmd).map_err(|e| {
+        error!("Error while constructing command, details:\n {}", e);
+        e
+    })?;
+
+    let session_id = child.id();
+    info!("Job session id: {} (attempt {})", session_id, attempt);
+    if let Some(lock) = lock {
+        lock.lock().unwrap().update(Some(session_id))?;
+    }
+
+    let limit = args.time_limit();
+    let grace = Duration::from_secs(args.kill_after.unwrap_or(DEFAULT_KILL_AFTER));
+    let mut supervisor = Supervisor::new(session_id, grace);
+    let descendants = if args.subreaper {
+        Some(Descendants::new(session_id, token))
+    } else {
+        None
+    };
+    supervisor.descendants = descendants.clone();
+    controller.start_attempt(attempt, supervisor.clone(), limit);
+
+    // supervising tasks will be stopped when the program exits
+    let (done_tx, done_rx) = oneshot::channel::<()>();
+    let done = done_rx.shared();
+
+    // When timeout, send TERM signal. The timeout could be extended through
+    // the controller in the meantime.
+    if limit.is_some() {
+        let (ctl, timed_out) = (controller.clone(), supervisor.clone());
+        let timeout = future::loop_fn((), move |_| {
+            let ctl = ctl.clone();
+            let at = ctl.deadline().unwrap_or_else(Instant::now);
+            Delay::new(at).map(move |_| match ctl.deadline() {
+                Some(d) if d > Instant::now() => future::Loop::Continue(()),
+                _ => future::Loop::Break(()),
+            })
+        })
+        .map_err(|e| error!("timer error: {}", e))
+        .map(move |_| {
+            error!(
+                "Command timeout after {} seconds!",
+                start.elapsed().as_secs()
+            );
+            timed_out.terminate(Termination::Timeout);
+        });
+        spawn_until(timeout, &done);
+    }
+
+    // Give the program a chance to checkpoint before timeout, following
+    // extensions of the timeout.
+    if limit.is_some() && args.on_timeout.action.is_some() {
+        let wdir = args.workdir()?;
+        let ctl = controller.clone();
+        let hook = args
+            .on_timeout
+            .schedule(session_id, move || ctl.deadline(), wdir);
+        spawn_until(hook, &done);
+    }
+
+    // When idle timeout, terminate the session as for timeout.
+    if let Some(t) = args.idle_timeout {
+        let activity = Activity::new();
+        if let Some(stdout) = child.take_stdout() {
+            activity.relay(stdout, std::io::stdout());
+        }
+        if let Some(stderr) = child.take_stderr() {
+            activity.relay(stderr, std::io::stderr());
+        }
+
+        let idle_timeout = Duration::from_secs(t);
+        let files = args.idle_watch_files();
+        let mut mtimes = vec![];
+        let stalled = supervisor.clone();
+        let idle = Interval::new_interval(Duration::from_secs(1))
+            .map_err(|e| error!("idle timer error: {}", e))
+            .for_each(move |_| {
+                activity.check_files(&files, &mut mtimes);
+                if activity.idle() >= idle_timeout {
+                    error!("Command stalled: no output in {} seconds!", t);
+                    stalled.terminate(Termination::Stalled);
+                    // stop watching
+                    Err(())
+                } else {
+                    Ok(())
+                }
+            });
+        spawn_until(idle, &done);
+    }
+
+    // clean up descendants left behind when the program exits
+    let reaped = match descendants {
+        Some(descendants) => future::Either::A(descendants.watch(grace).then(|_| Ok(()))),
+        None => future::Either::B(future::ok::<(), Error>(())),
+    };
+
+    let (args, controller) = (args.clone(), controller.clone());
+    let outcome = child
+        .map_err(|e| {
+            error!("cmd failed with errors\n: {}", e);
+            Error::from(e)
+        })
+        .join(reaped)
+        .and_then(move |(outcome, _)| {
+            // stop supervising, and wait for termination in progress to
+            // finish off the surviving processes
+            let _ = done_tx.send(());
+            controller.finish_attempt();
+            supervisor
+                .escalated()
+                .then(move |_| Ok((outcome, supervisor)))
+        })
+        .map(move |((status, usage), supervisor)| {
+            println!("exit status: {:?}", status);
+            println!(
+                "cpu time: {:.2}s user, {:.2}s sys; peak rss: {} kB; processes: {}",
+                usage.user_time, usage.sys_time, usage.peak_rss, usage.nprocs
+            );
+
+            let mut termination = supervisor.termination();
+            if termination == Termination::Normal && args.limits.exceeded_by(&status, &usage) {
+                error!("Killed by resource limit.");
+                termination = Termination::ResourceLimit;
+            }
+
+            Attempt {
+                attempt,
+                start_time,
+                end_time: timestamp_now(),
+                wall_time: start.elapsed().as_secs_f64(),
+                exit_code: status.code(),
+                signal: status.signal(),
+                termination,
+                usage,
+            }
+        });
+
+    Ok(Box::new(outcome))
+}
+// tokio:1 ends here
+
+// control
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*control][control:1]]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Status of a run reported through the control socket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlStatus {
+    /// Process id of the runner
+    pub pid: u32,
+    /// Session id of the running program
+    pub session: Option<u32>,
+    /// Number of the running or latest attempt
+    pub attempt: usize,
+    pub state: RunState,
+    /// Seconds remaining before timeout
+    pub remaining: Option<f64>,
+}
+
+impl RunController {
+    /// Apply a control command, such as "pause" or "extend-timeout 1h".
+    /// Return the reply message.
+    pub fn apply_command(&self, cmd: &str) -> Result<String> {
+        let mut parts = cmd.split_whitespace();
+        let reply = match (parts.next(), parts.next(), parts.next()) {
+            (Some("pause"), None, None) => format!("paused: {:?}", self.pause()?),
+            (Some("resume"), None, None) => format!("resumed: {:?}", self.resume()?),
+            (Some("terminate"), None, None) => {
+                info!("Terminate on request from control socket.");
+                self.terminate();
+                "terminating".into()
+            }
+            (Some("extend-timeout"), Some(t), None) => {
+                let t = parse_duration(t).map_err(|e| format_err!("{}", e))?;
+                match self.extend_timeout(Duration::from_secs(t)) {
+                    Some(remaining) => {
+                        info!("Timeout extended by {} seconds.", t);
+                        format!("timeout in {} seconds", remaining.as_secs())
+                    }
+                    None => bail!("no timeout to extend"),
+                }
+            }
+            (Some("status"), None, None) => serde_json::to_string_pretty(&self.status())?,
+            _ => bail!("invalid command: {:?}", cmd),
+        };
+
+        Ok(reply)
+    }
+}
+
+/// Timeout for reading a command from, or writing the reply to a control
+/// connection.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A Unix domain socket accepting commands to control a run. The listener
+/// will be stopped and the socket file removed on drop.
+#[derive(Debug)]
+pub struct ControlSocket {
+    path: PathBuf,
+    stopped: Arc<AtomicBool>,
+    listener: Option<std::thread::JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    /// Listen on `path` for commands, which will be applied using
+    /// `controller` in a background thread.
+    pub fn bind<P: AsRef<Path>>(path: P, controller: RunController) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let path = path.as_ref().to_owned();
+        // remove stale socket left by previous runs, but nothing else
+        if let Ok(m) = std::fs::symlink_metadata(&path) {
+            if !m.file_type().is_socket() {
+                bail!("Not a socket: {}", path.display());
+            }
+            // nobody listening on a stale socket
+            match UnixStream::connect(&path) {
+                Ok(_) => bail!("Socket in use by another runner: {}", path.display()),
+                Err(ref e) if e.raw_os_error() == Some(libc::ECONNREFUSED) => {
+                    std::fs::remove_file(&path)?;
+                }
+                Err(e) => bail!("Failed to check socket {}: {}", path.display(), e),
+            }
+        }
+
+        let listener = UnixListener::bind(&path)?;
+        // poll for connections, so that the listener could be stopped
+        listener.set_nonblocking(true)?;
+        info!("Accept control commands on {}", path.display());
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        let handle = std::thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        // a slow client should not block others
+                        let controller = controller.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = handle_control(stream, &controller) {
+                                warn!("Control connection error: {}", e);
+                            }
+                        });
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                    Err(e) => {
+                        error!("Control socket error: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path,
+            stopped,
+            listener: Some(handle),
+        })
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.listener.take() {
+            let _ = handle.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reply to one command per connection. Errors are replied with an "error: "
+/// prefix.
+fn handle_control(stream: UnixStream, controller: &RunController) -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+
+    let mut line = String::new();
+    std::io::BufReader::new(&stream).read_line(&mut line)?;
+    let reply = match controller.apply_command(line.trim()) {
+        Ok(reply) => reply,
+        Err(e) => format!("error: {}", e),
+    };
+    writeln!(&stream, "{}", reply)?;
+
+    Ok(())
+}
+
+/// Send `cmd` to the control socket at `path`. Return the reply.
+pub fn send_control<P: AsRef<Path>>(path: P, cmd: &str) -> Result<String> {
+    use std::io::{Read, Write};
+
+    let mut stream = UnixStream::connect(path.as_ref())
+        .map_err(|e| format_err!("Failed to connect {}: {}", path.as_ref().display(), e))?;
+    writeln!(stream, "{}", cmd)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+// control:1 ends here
+
+// attach
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*attach][attach:1]]
+/// Supervise a program already running, e.g. a calculation launched by hand
+#[derive(StructOpt, Debug, Clone)]
+pub struct Attach {
+    /// Process id of the running program, which should be a session leader,
+    /// or id of its session
+    #[structopt(name = "PID")]
+    pid: u32,
+
+    /// Supervise the whole session of PID even if it is not the session
+    /// leader. Beware that the session could be your login shell.
+    #[structopt(long = "session")]
+    session: bool,
+
+    /// Job timeout in seconds, or in human-friendly form such as 2h30m
+    #[structopt(long = "timeout", short = "t", parse(try_from_str = "parse_duration"))]
+    timeout: Option<u64>,
+
+    /// Terminate the program at DEADLINE, such as "2019-07-26 08:00" or
+    /// "08:00" for the next 8 o'clock
+    #[structopt(
+        long = "deadline",
+        value_name = "DEADLINE",
+        parse(try_from_str = "parse_deadline")
+    )]
+    deadline: Option<DateTime<Local>>,
+
+    /// Grace period in seconds before killing processes that survive
+    /// SIGTERM. The default is 30 seconds.
+    #[structopt(long = "kill-after", parse(try_from_str = "parse_duration"))]
+    kill_after: Option<u64>,
+
+    /// Write a run report in JSON format to FILE
+    #[structopt(long = "report", value_name = "FILE", parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    /// Write run status in JSON format to FILE periodically
+    #[structopt(long = "status-file", value_name = "FILE", parse(from_os_str))]
+    status_file: Option<PathBuf>,
+
+    /// Interval in seconds for updating the status file
+    #[structopt(long = "status-interval", value_name = "SECONDS", default_value = "10")]
+    status_interval: u64,
+
+    #[structopt(flatten)]
+    forward_signals: ForwardSignals,
+}
+
+impl Attach {
+    /// Supervise the program until it is done
+    pub fn run(&self) -> Result<RunReport> {
+        attach(&self)
+    }
+
+    pub fn new(pid: u32) -> Self {
+        Self {
+            pid,
+            session: false,
+            timeout: None,
+            deadline: None,
+            kill_after: None,
+            report: None,
+            status_file: None,
+            status_interval: 10,
+            forward_signals: ForwardSignals::default(),
+        }
+    }
+
+    /// Supervise the whole session even if the process is not the session
+    /// leader
+    pub fn with_session(mut self) -> Self {
+        self.session = true;
+        self
+    }
+
+    /// Set timeout
+    pub fn with_timeout(mut self, t: u64) -> Self {
+        self.timeout = Some(t);
+        self
+    }
+
+    /// Terminate the program at `deadline`
+    pub fn with_deadline(mut self, deadline: DateTime<Local>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set grace period before killing processes that survive SIGTERM
+    pub fn with_kill_after(mut self, t: u64) -> Self {
+        self.kill_after = Some(t);
+        self
+    }
+
+    /// Write a run report in JSON format to `path`
+    pub fn with_report<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.report = Some(path.as_ref().into());
+        self
+    }
+
+    /// Write run status to file every `interval` seconds
+    pub fn with_status_file<P: AsRef<Path>>(mut self, path: P, interval: u64) -> Self {
+        self.status_file = Some(path.as_ref().into());
+        self.status_interval = interval;
+        self
+    }
+
+    /// Find the session to be supervised.
+    fn session_id(&self) -> Result<u32> {
+        let sid = match ProcessStat::from_pid(self.pid) {
+            Some(ref p) if p.session == p.pid => p.session,
+            // widening to the whole session could kill unrelated processes
+            Some(ref p) if !self.session => bail!(
+                "Process {} is not a session leader, but a member of session {}. \
+                 Use --session to supervise the whole session.",
+                p.pid,
+                p.session
+            ),
+            Some(p) => p.session,
+            // the session leader could have exited
+            None if !alive_session_members(self.pid)?.is_empty() => self.pid,
+            None => bail!("No running process or session found: {}", self.pid),
+        };
+
+        // terminating our own session will kill ourselves
+        if ProcessStat::from_pid(process::id()).map(|p| p.session) == Some(sid) {
+            bail!("Refuse to attach to our own session: {}", sid);
+        }
+
+        Ok(sid)
+    }
+}
+
+/// Supervise a running session until all processes in it exit, terminating
+/// the session when timeout or interrupted.
+pub fn attach(args: &Attach) -> Result<RunReport> {
+    // show program status
+    let app_name = format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"),);
+    let start_time = timestamp_now();
+    println!("{} starts at {}", app_name, start_time);
+    debug!("{:?}", args);
+    let start = Instant::now();
+
+    let sid = args.session_id()?;
+    info!("Attached to session {}", sid);
+
+    let limit = wall_time_limit(args.timeout, args.deadline);
+    let grace = Duration::from_secs(args.kill_after.unwrap_or(DEFAULT_KILL_AFTER));
+    let supervisor = Supervisor::new(sid, grace);
+    let mut rt = tokio::runtime::Runtime::new()?;
+
+    // When timeout, send TERM signal.
+    if let Some(t) = limit {
+        let timed_out = supervisor.clone();
+        let timeout = Delay::new(Instant::now() + t)
+            .map_err(|e| error!("timer error: {}", e))
+            .map(move |_| {
+                error!("Session timeout after {} seconds!", t.as_secs());
+                timed_out.terminate(Termination::Timeout);
+            });
+        rt.spawn(timeout);
+    }
+
+    // Terminate the session on the first signal, and kill it on the next.
+    let (interrupted, killed) = (supervisor.clone(), supervisor.clone());
+    rt.spawn(handle_interrupts(
+        move || interrupted.terminate(Termination::Interrupted),
+        move || killed.kill(Termination::Interrupted),
+    ));
+
+    // Relay other signals to the program.
+    rt.spawn(args.forward_signals.relay(move || Some(sid)));
+
+    // Update status file periodically.
+    let heartbeat = args
+        .status_file
+        .as_ref()
+        .map(|path| Arc::new(Mutex::new(Heartbeat::new(path, &start_time))));
+    if let Some(heartbeat) = heartbeat.clone() {
+        let deadline = limit.map(|t| Instant::now() + t);
+        rt.spawn(keep_heartbeat(
+            heartbeat,
+            args.status_interval,
+            move || Some(sid),
+            move || deadline,
+        ));
+    }
+
+    let usage = rt.block_on(watch_session(sid))?;
+    // let the escalation in progress report the force-killed processes
+    let _ = rt.block_on(supervisor.escalated());
+    let _ = rt.shutdown_now().wait();
+    println!(
+        "cpu time: {:.2}s user, {:.2}s sys; peak rss: {} kB; processes: {}",
+        usage.user_time, usage.sys_time, usage.peak_rss, usage.nprocs
+    );
+
+    let attempt = Attempt {
+        attempt: 1,
+        start_time: start_time.clone(),
+        end_time: timestamp_now(),
+        wall_time: start.elapsed().as_secs_f64(),
+        exit_code: None,
+        signal: None,
+        termination: supervisor.termination(),
+        usage,
+    };
+    let report = RunReport::new(start_time, start.elapsed().as_secs_f64(), vec![attempt]);
+    if let Some(heartbeat) = &heartbeat {
+        heartbeat.lock().unwrap().finish(&report)?;
+    }
+    if let Some(path) = &args.report {
+        report.save(path)?;
+        info!("Run report wrote to: {}", path.display());
+    }
+
+    Ok(report)
+}
+// attach:1 ends here
+
+// batch
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*batch][batch:1]]
+/// Run a list of commands in parallel, each in its own session
+#[derive(StructOpt, Debug, Clone)]
+pub struct Batch {
+    /// File with one shell command per line, or "-" for stdin. Empty lines
+    /// and lines starting with # are ignored.
+    #[structopt(name = "FILE", parse(from_os_str), default_value = "-")]
+    file: PathBuf,
+
+    /// Number of commands running at the same time
+    #[structopt(short = "j", long = "jobs", value_name = "N", default_value = "1")]
+    jobs: usize,
+
+    /// Timeout for each command in seconds, or in human-friendly form such
+    /// as 2h30m
+    #[structopt(long = "timeout", short = "t", parse(try_from_str = "parse_duration"))]
+    timeout: Option<u64>,
+
+    /// Grace period in seconds before killing processes that survive
+    /// SIGTERM. The default is 30 seconds.
+    #[structopt(long = "kill-after", parse(try_from_str = "parse_duration"))]
+    kill_after: Option<u64>,
+
+    /// Write stdout and stderr of each command to DIR
+    #[structopt(
+        long = "log-dir",
+        value_name = "DIR",
+        parse(from_os_str),
+        default_value = "."
+    )]
+    log_dir: PathBuf,
+
+    /// Write a batch report in JSON format to FILE
+    #[structopt(long = "report", value_name = "FILE", parse(from_os_str))]
+    report: Option<PathBuf>,
+}
+
+impl Batch {
+    /// Run all commands until done
+    pub fn run(&self) -> Result<BatchReport> {
+        batch(&self)
+    }
+
+    pub fn new<P: AsRef<Path>>(file: P) -> Self {
+        Self {
+            file: file.as_ref().into(),
+            jobs: 1,
+            timeout: None,
+            kill_after: None,
+            log_dir: ".".into(),
+            report: None,
+        }
+    }
+
+    /// Run `n` commands at the same time
+    pub fn with_jobs(mut self, n: usize) -> Self {
+        self.jobs = n;
+        self
+    }
+
+    /// Set timeout for each command
+    pub fn with_timeout(mut self, t: u64) -> Self {
+        self.timeout = Some(t);
+        self
+    }
+
+    /// Set grace period before killing processes that survive SIGTERM
+    pub fn with_kill_after(mut self, t: u64) -> Self {
+        self.kill_after = Some(t);
+        self
+    }
+
+    /// Write log files of commands to `dir`
+    pub fn with_log_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.log_dir = dir.as_ref().into();
+        self
+    }
+
+    /// Write a batch report in JSON format to `path`
+    pub fn with_report<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.report = Some(path.as_ref().into());
+        self
+    }
+
+    /// Read commands from file or stdin.
+    fn read_commands(&self) -> Result<Vec<String>> {
+        use std::io::Read;
+
+        let mut text = String::new();
+        if self.file == Path::new("-") {
+            std::io::stdin().read_to_string(&mut text)?;
+        } else {
+            text = std::fs::read_to_string(&self.file)?;
+        }
+
+        let commands = text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_owned())
+            .collect();
+
+        Ok(commands)
+    }
+
+    /// Create a runner for `item`.
+    fn runner(&self, item: &BatchItem) -> Runner {
+        let mut runner = Runner::new("sh")
+            .with_arg("-c")
+            .with_arg(&item.command)
+            .with_stdout(&item.stdout)
+            .with_stderr(&item.stderr)
+            // signals are handled for all commands by the batch
+            .with_forward_signals(vec![]);
+        if let Some(t) = self.timeout {
+            runner = runner.with_timeout(t);
+        }
+        if let Some(t) = self.kill_after {
+            runner = runner.with_kill_after(t);
+        }
+
+        runner
+    }
+}
+
+/// Outcome of a command in a batch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItem {
+    /// Line number of the command counted from 1, skipping ignored lines
+    pub index: usize,
+    pub command: String,
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+    /// Run report of the command, or None if it was not run
+    pub report: Option<RunReport>,
+    /// Error preventing the command from running
+    pub error: Option<String>,
+}
+
+impl BatchItem {
+    fn new(index: usize, command: String, log_dir: &Path) -> Self {
+        Self {
+            index,
+            command,
+            stdout: log_dir.join(format!("{}.out", index)),
+            stderr: log_dir.join(format!("{}.err", index)),
+            report: None,
+            error: None,
+        }
+    }
+
+    /// Return a short description of the outcome.
+    pub fn outcome(&self) -> &'static str {
+        match (&self.report, &self.error) {
+            (_, Some(_)) => "error",
+            (None, None) => "cancelled",
+            (Some(r), None) => match r.termination {
+                Termination::Normal if r.code() == 0 => "ok",
+                Termination::Normal => "failed",
+                Termination::Timeout => "timeout",
+                Termination::Interrupted => "interrupted",
+                Termination::ResourceLimit => "resource_limit",
+                Termination::Stalled => "stalled",
+            },
+        }
+    }
+
+    /// Test if the command succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.outcome() == "ok"
+    }
+}
+
+/// Report of a batch run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub start_time: String,
+    pub end_time: String,
+    /// Wall time in seconds
+    pub wall_time: f64,
+    pub items: Vec<BatchItem>,
+}
+
+impl BatchReport {
+    /// Return exit code for the batch: 0 if all commands succeeded.
+    pub fn code(&self) -> i32 {
+        if self.items.iter().all(|x| x.is_ok()) {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Print a summary table of all commands.
+    pub fn print_summary(&self) {
+        println!(
+            "{:>5} {:>14} {:>5} {:>10} COMMAND",
+            "#", "OUTCOME", "CODE", "TIME(s)"
+        );
+        for item in &self.items {
+            let (code, time) = match &item.report {
+                Some(r) => (r.code().to_string(), format!("{:.1}", r.wall_time)),
+                None => ("-".into(), "-".into()),
+            };
+            println!(
+                "{:>5} {:>14} {:>5} {:>10} {}",
+                item.index,
+                item.outcome(),
+                code,
+                time,
+                item.command
+            );
+        }
+
+        let nok = self.items.iter().filter(|x| x.is_ok()).count();
+        println!(
+            "{} of {} commands succeeded in {:.1} seconds.",
+            nok,
+            self.items.len(),
+            self.wall_time
+        );
+    }
+
+    /// Save report in JSON format to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Run commands in parallel with at most `args.jobs` at the same time.
+/// Interruption terminates all running commands and cancels the rest.
+pub fn batch(args: &Batch) -> Result<BatchReport> {
+    let commands = args.read_commands()?;
+    std::fs::create_dir_all(&args.log_dir)?;
+    info!("Run {} commands with {} jobs", commands.len(), args.jobs);
+
+    let start_time = timestamp_now();
+    let start = Instant::now();
+
+    // controllers of running commands
+    let active = Arc::new(Mutex::new(
+        std::collections::HashMap::<usize, RunController>::new(),
+    ));
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+
+    // Terminate all commands on the first signal, and kill them on the next.
+    let sig_int = Signal::new(SIGINT).flatten_stream();
+    let sig_term = Signal::new(SIGTERM).flatten_stream();
+    let (running, stop) = (active.clone(), cancelled.clone());
+    let signals = sig_int
+        .select(sig_term)
+        .map_err(|e| error!("signal error: {}", e))
+        .for_each(move |_| {
+            let killing = stop.swap(true, Ordering::SeqCst);
+            if killing {
+                println!("Kill running processes immediately ...");
+            } else {
+                println!("User interrupted.");
+            }
+            for ctl in running.lock().unwrap().values() {
+                if !killing {
+                    ctl.terminate();
+                } else if let Err(e) = ctl.kill() {
+                    error!("{}", e);
+                }
+            }
+            Ok(())
+        });
+    rt.spawn(signals);
+
+    let args_ = args.clone();
+    let tasks = stream::iter_ok::<_, Error>(commands.into_iter().enumerate())
+        .map(move |(i, command)| {
+            let mut item = BatchItem::new(i + 1, command, &args_.log_dir);
+            if cancelled.load(Ordering::SeqCst) {
+                return future::Either::A(future::ok(item));
+            }
+
+            let runner = args_.runner(&item);
+            let (active, cancelled, index) = (active.clone(), cancelled.clone(), item.index);
+            let running = active.clone();
+            let task = future::lazy(move || runner.spawn())
+                .and_then(move |handle| {
+                    let ctl = handle.controller();
+                    running.lock().unwrap().insert(index, ctl.clone());
+                    // interrupted while spawning
+                    if cancelled.load(Ordering::SeqCst) {
+                        ctl.terminate();
+                    }
+                    handle
+                })
+                .then(move |r| {
+                    active.lock().unwrap().remove(&index);
+                    match r {
+                        Ok(report) => item.report = Some(report),
+                        Err(e) => {
+                            error!("Command {} failed to run: {}", index, e);
+                            item.error = Some(e.to_string());
+                        }
+                    }
+                    Ok(item)
+                });
+            future::Either::B(task)
+        })
+        .buffer_unordered(args.jobs.max(1))
+        .collect();
+
+    let mut items = rt.block_on(tasks)?;
+    let _ = rt.shutdown_now().wait();
+    items.sort_by_key(|x| x.index);
+
+    let report = BatchReport {
+        start_time,
+        end_time: timestamp_now(),
+        wall_time: start.elapsed().as_secs_f64(),
+        items,
+    };
+    if let Some(path) = &args.report {
+        report.save(path)?;
+        info!("Batch report wrote to: {}", path.display());
+    }
+
+    Ok(report)
+}
+// batch:1 ends here
+
+// utils
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*utils][utils:1]]
+/// terminate child processes in a session.
+pub fn terminate_session(sid: u32) -> Result<Vec<u32>> {
+    signal_processes_by_session_id(sid, libc::SIGTERM)
+}
+
+/// Kill processes in a session.
+pub fn kill_session(sid: u32) -> Result<Vec<u32>> {
+    signal_processes_by_session_id(sid, libc::SIGKILL)
+}
+
+/// Resume processes in a session.
+pub fn resume_session(sid: u32) -> Result<Vec<u32>> {
+    signal_processes_by_session_id(sid, libc::SIGCONT)
+}
+
+/// Pause processes in a session.
+pub fn pause_session(sid: u32) -> Result<Vec<u32>> {
+    signal_processes_by_session_id(sid, libc::SIGSTOP)
+}
+
+/// Terminate processes in a session, and kill those still alive after `grace`
+/// period. Return ids of force-killed processes.
+pub fn terminate_session_gracefully(sid: u32, grace: Duration) -> Result<Vec<u32>> {
+    // keep going even if some processes could not be terminated: they are
+    // what the escalation to SIGKILL is for.
+    if let Err(e) = terminate_session(sid) {
+        warn!("Failed to terminate session {}: {}", sid, e);
+    }
+
+    kill_session_after(sid, grace)
+}
+
+/// Wait `grace` period for processes in a session to exit, and kill those
+/// still alive. Return ids of force-killed processes.
+pub fn kill_session_after(sid: u32, grace: Duration) -> Result<Vec<u32>> {
+    let start = std::time::Instant::now();
+    loop {
+        let alive = alive_session_members(sid)?;
+        if alive.is_empty() {
+            return Ok(vec![]);
+        }
+        if start.elapsed() >= grace {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // processes could exit in the meantime, so we check again
+    let killed = signal_processes(alive_session_members(sid)?, libc::SIGKILL)?;
+    if !killed.is_empty() {
+        warn!(
+            "Force killed processes in session {} after {:?}: {:?}",
+            sid, grace, killed
+        );
+    }
+
+    Ok(killed)
+}
+
+/// signal processes by session id. Return ids of signalled processes.
+fn signal_processes_by_session_id(sid: u32, signal: libc::c_int) -> Result<Vec<u32>> {
+    let pids = session_members(sid)?;
+    if pids.is_empty() {
+        info!("No remaining processes found!");
+    }
+
+    signal_processes(pids, signal)
+}
+
+/// Send `signal` to every process in `pids`, even if some of them fail. Return
+/// ids of signalled processes, or the first failure after trying them all.
+fn signal_processes(pids: Vec<u32>, signal: libc::c_int) -> Result<Vec<u32>> {
+    let mut signalled = vec![];
+    let mut failures = vec![];
+    for pid in pids {
+        match signal_process(pid, signal) {
+            Ok(true) => signalled.push(pid),
+            Ok(false) => {}
+            Err(e) => {
+                warn!("{}", e);
+                failures.push(e);
+            }
+        }
+    }
+
+    match failures.into_iter().next() {
+        Some(e) => Err(e.into()),
+        None => Ok(signalled),
+    }
+}
+// utils:1 ends here
+
+// session
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*session][session:1]]
+/// Process status parsed from `/proc/[pid]/stat`.
+#[derive(Debug, Clone)]
+pub struct ProcessStat {
+    /// Process id
+    pub pid: u32,
+    /// File name of the executable
+    pub comm: String,
+    /// Process state: R, S, D, Z, T, ...
+    pub state: char,
+    /// Parent process id
+    pub ppid: u32,
+    /// Process group id
+    pub pgrp: u32,
+    /// Session id
+    pub session: u32,
+    /// User mode CPU time in clock ticks
+    pub utime: u64,
+    /// Kernel mode CPU time in clock ticks
+    pub stime: u64,
+    /// Resident set size in pages
+    pub rss: u64,
+}
+
+impl ProcessStat {
+    /// Read status of process `pid` from `/proc`. Return None if the process
+    /// does not exist any more.
+    pub fn from_pid(pid: u32) -> Option<Self> {
+        let s = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        Self::parse(&s)
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        // comm is enclosed in parentheses, which could contain whitespaces or
+        // parentheses itself.
+        let lpos = s.find('(')?;
+        let rpos = s.rfind(')')?;
+        let pid = s[..lpos].trim().parse().ok()?;
+        let comm = s[lpos + 1..rpos].to_string();
+
+        // fields starting from the 3rd one (state), see proc(5) for details
+        let fields: Vec<_> = s[rpos + 1..].split_whitespace().collect();
+        let field = |n: usize| fields.get(n - 3).and_then(|x| x.parse::<u64>().ok());
+        let state = fields.get(0)?.chars().next()?;
+
+        Some(Self {
+            pid,
+            comm,
+            state,
+            ppid: field(4)? as u32,
+            pgrp: field(5)? as u32,
+            session: field(6)? as u32,
+            utime: field(14)?,
+            stime: field(15)?,
+            rss: field(24)?,
+        })
+    }
+
+    /// Return resident set size in kilobytes.
+    pub fn rss_kb(&self) -> u64 {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        self.rss * page_size / 1024
+    }
+
+    /// Test if the process has exited but not reaped yet.
+    pub fn is_zombie(&self) -> bool {
+        self.state == 'Z'
+    }
+}
+
+/// Return status of all processes found in `/proc`.
+pub fn all_processes() -> Result<Vec<ProcessStat>> {
+    let mut list = vec![];
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        if let Some(pid) = entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            // the process could be gone during scanning
+            if let Some(stat) = ProcessStat::from_pid(pid) {
+                list.push(stat);
+            }
+        }
+    }
+
+    Ok(list)
+}
+
+/// Return ids of processes in session `sid`.
+pub fn session_members(sid: u32) -> Result<Vec<u32>> {
+    let pids = all_processes()?
+        .into_iter()
+        .filter(|p| p.session == sid)
+        .map(|p| p.pid)
+        .collect();
+
+    Ok(pids)
+}
+
+/// Return ids of processes in session `sid`, excluding zombies.
+fn alive_session_members(sid: u32) -> Result<Vec<u32>> {
+    let pids = all_processes()?
+        .into_iter()
+        .filter(|p| p.session == sid && !p.is_zombie())
+        .map(|p| p.pid)
+        .collect();
+
+    Ok(pids)
+}
+
+/// Parse a signal from its name (USR1 or SIGUSR1) or number.
+pub fn parse_signal(s: &str) -> Option<libc::c_int> {
+    if let Ok(n) = s.parse() {
+        return Some(n);
+    }
+
+    let name = s.to_uppercase();
+    let signal = match name.trim_start_matches("SIG") {
+        "HUP" => libc::SIGHUP,
+        "INT" => libc::SIGINT,
+        "QUIT" => libc::SIGQUIT,
+        "KILL" => libc::SIGKILL,
+        "USR1" => libc::SIGUSR1,
+        "USR2" => libc::SIGUSR2,
+        "ALRM" => libc::SIGALRM,
+        "TERM" => libc::SIGTERM,
+        "CONT" => libc::SIGCONT,
+        "STOP" => libc::SIGSTOP,
+        "TSTP" => libc::SIGTSTP,
+        "XCPU" => libc::SIGXCPU,
+        "XFSZ" => libc::SIGXFSZ,
+        "WINCH" => libc::SIGWINCH,
+        _ => return None,
+    };
+
+    Some(signal)
+}
+
+/// Parse a signal name or number from command line.
+pub fn parse_signal_arg(s: &str) -> std::result::Result<libc::c_int, String> {
+    parse_signal(s).ok_or_else(|| format!("invalid signal: {}", s))
+}
+
+/// Return ids of processes in process group `pgid`.
+pub fn process_group_members(pgid: u32) -> Result<Vec<u32>> {
+    let pids = all_processes()?
+        .into_iter()
+        .filter(|p| p.pgrp == pgid)
+        .map(|p| p.pid)
+        .collect();
+
+    Ok(pids)
+}
+
+/// Error when sending a signal to a process.
+#[derive(Debug)]
+pub struct SignalError {
+    /// The process to be signalled.
+    pub pid: u32,
+    /// The signal number.
+    pub signal: libc::c_int,
+    /// The underlying OS error.
+    pub source: std::io::Error,
+}
+
+impl std::fmt::Display for SignalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to send signal {} to process {}: {}",
+            self.signal, self.pid, self.source
+        )
+    }
+}
+
+impl std::error::Error for SignalError {}
+
+/// Send `signal` to process `pid`. Return false if the process has gone.
+pub fn signal_process(pid: u32, signal: libc::c_int) -> std::result::Result<bool, SignalError> {
+    let rc = unsafe { libc::kill(pid as libc::pid_t, signal) };
+    if rc == 0 {
+        return Ok(true);
+    }
+
+    let source = std::io::Error::last_os_error();
+    if source.raw_os_error() == Some(libc::ESRCH) {
+        Ok(false)
+    } else {
+        Err(SignalError {
+            pid,
+            signal,
+            source,
+        })
+    }
+}
+// session:1 ends here
+
+// tree
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*tree][tree:1]]
+/// Information of a process for display.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    /// Process id
+    pub pid: u32,
+    /// Parent process id
+    pub ppid: u32,
+    /// Session id
+    pub session: u32,
+    /// Process state: R, S, D, Z, T, ...
+    pub state: char,
+    /// Resident set size in kilobytes
+    pub rss: u64,
+    /// Full command line
+    pub cmdline: String,
+}
+
+impl ProcessInfo {
+    fn new(stat: &ProcessStat) -> Self {
+        // cmdline is empty for zombies and kernel threads
+        let cmdline = std::fs::read(format!("/proc/{}/cmdline", stat.pid))
+            .ok()
+            .map(|raw| {
+                let args: Vec<_> = raw
+                    .split(|&b| b == 0)
+                    .filter(|x| !x.is_empty())
+                    .map(|x| String::from_utf8_lossy(x).into_owned())
+                    .collect();
+                args.join(" ")
+            })
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| format!("[{}]", stat.comm));
+
+        Self {
+            pid: stat.pid,
+            ppid: stat.ppid,
+            session: stat.session,
+            state: stat.state,
+            rss: stat.rss_kb(),
+            cmdline,
+        }
+    }
+}
+
+/// A snapshot of the process tree built from `/proc`.
+#[derive(Debug, Clone)]
+pub struct ProcessTree {
+    procs: std::collections::HashMap<u32, ProcessStat>,
+    children: std::collections::HashMap<u32, Vec<u32>>,
+}
+
+impl ProcessTree {
+    /// Take a snapshot of all processes in the system.
+    pub fn snapshot() -> Result<Self> {
+        let mut procs = std::collections::HashMap::new();
+        let mut children = std::collections::HashMap::new();
+        for p in all_processes()? {
+            children.entry(p.ppid).or_insert_with(Vec::new).push(p.pid);
+            procs.insert(p.pid, p);
+        }
+
+        Ok(Self { procs, children })
+    }
+
+    /// Return ids of descendants of process `pid`, excluding itself.
+    pub fn descendant_ids(&self, pid: u32) -> Vec<u32> {
+        let mut ids = vec![];
+        let mut stack = vec![pid];
+        while let Some(p) = stack.pop() {
+            if let Some(kids) = self.children.get(&p) {
+                ids.extend(kids);
+                stack.extend(kids);
+            }
+        }
+        ids.sort();
+        ids
+    }
+
+    /// Return descendants of process `pid`, excluding itself.
+    pub fn descendants(&self, pid: u32) -> Vec<ProcessInfo> {
+        self.infos(self.descendant_ids(pid))
+    }
+
+    /// Return processes in session `sid`.
+    pub fn session(&self, sid: u32) -> Vec<ProcessInfo> {
+        let mut ids: Vec<_> = self
+            .procs
+            .values()
+            .filter(|p| p.session == sid)
+            .map(|p| p.pid)
+            .collect();
+        ids.sort();
+        self.infos(ids)
+    }
+
+    /// Send `signal` to process `pid` and all its descendants. Return ids of
+    /// signalled processes.
+    pub fn signal_subtree(&self, pid: u32, signal: libc::c_int) -> Result<Vec<u32>> {
+        // signal parent first to prevent it from spawning new children
+        let pids = std::iter::once(pid)
+            .chain(self.descendant_ids(pid))
+            .collect();
+        signal_processes(pids, signal)
+    }
+
+    fn infos(&self, ids: Vec<u32>) -> Vec<ProcessInfo> {
+        ids.iter()
+            .filter_map(|pid| self.procs.get(pid))
+            .map(ProcessInfo::new)
+            .collect()
+    }
+}
+// tree:1 ends here
+
+// reaper
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*reaper][reaper:1]]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Environment variable marking descendants of a tracked session.
+const TRACK_ENV: &str = "RUNNER_TRACK_ID";
+
+/// Make current process a subreaper, so that orphaned descendants will be
+/// reparented to it instead of init.
+pub fn set_child_subreaper() -> Result<()> {
+    let rc = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) };
+    if rc < 0 {
+        bail!(
+            "Failed to become a child subreaper: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    Ok(())
+}
+
+/// Return a unique token for marking descendants of a program.
+fn track_token() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Descendants of a session leader, including those escaped from the session
+/// by calling setsid or double forking. Orphans can only be found when
+/// current process is a subreaper.
+#[derive(Debug, Clone)]
+pub struct Descendants {
+    /// process id of the session leader
+    leader: u32,
+    /// value of `TRACK_ENV` inherited by descendants
+    token: String,
+    /// live descendants found in the latest scan
+    procs: Arc<Mutex<std::collections::HashMap<u32, ProcessStat>>>,
+}
+
+impl Descendants {
+    pub fn new(leader: u32, token: String) -> Self {
+        Self {
+            leader,
+            token,
+            procs: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        }
+    }
+
+    /// Test if orphan `pid` was marked with our token.
+    fn is_marked(&self, pid: u32) -> bool {
+        let mark = format!("{}={}", TRACK_ENV, self.token);
+        std::fs::read(format!("/proc/{}/environ", pid))
+            .map(|raw| raw.split(|&b| b == 0).any(|x| x == mark.as_bytes()))
+            .unwrap_or(false)
+    }
+
+    /// Scan `/proc` for descendants, and reap those orphaned to us. Return
+    /// false if the session leader has exited.
+    fn update(&self) -> Result<bool> {
+        let tree = ProcessTree::snapshot()?;
+        let me = process::id();
+        let mut procs = self.procs.lock().unwrap();
+
+        // session members, descendants of known processes and orphans marked
+        // by us
+        let mut found: HashSet<u32> = tree
+            .procs
+            .values()
+            .filter(|p| p.session == self.leader)
+            .map(|p| p.pid)
+            .collect();
+        for pid in std::iter::once(self.leader).chain(procs.keys().cloned()) {
+            if tree.procs.contains_key(&pid) {
+                found.insert(pid);
+                found.extend(tree.descendant_ids(pid));
+            }
+        }
+        if let Some(orphans) = tree.children.get(&me) {
+            for &pid in orphans {
+                if pid != self.leader && !found.contains(&pid) && self.is_marked(pid) {
+                    found.insert(pid);
+                    found.extend(tree.descendant_ids(pid));
+                }
+            }
+        }
+        found.remove(&self.leader);
+
+        let mut current = std::collections::HashMap::new();
+        for pid in found {
+            let p = &tree.procs[&pid];
+            if p.session != self.leader && !procs.contains_key(&pid) {
+                warn!(
+                    "Process {} ({}) found outside session {}",
+                    pid, p.comm, self.leader
+                );
+            }
+            // reap orphans reparented to us
+            if p.ppid == me && p.is_zombie() {
+                let mut status = 0;
+                unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) };
+                continue;
+            }
+            current.insert(pid, p.clone());
+        }
+        *procs = current;
+
+        let leader_alive = tree
+            .procs
+            .get(&self.leader)
+            .map_or(false, |p| !p.is_zombie());
+        Ok(leader_alive)
+    }
+
+    /// Return ids of live descendants outside the session.
+    fn escaped(&self) -> Vec<u32> {
+        let procs = self.procs.lock().unwrap();
+        let mut pids: Vec<_> = procs
+            .values()
+            .filter(|p| p.session != self.leader && !p.is_zombie())
+            .map(|p| p.pid)
+            .collect();
+        pids.sort();
+        pids
+    }
+
+    /// Return ids of all live descendants.
+    fn alive(&self) -> Vec<u32> {
+        let procs = self.procs.lock().unwrap();
+        let mut pids: Vec<_> = procs
+            .values()
+            .filter(|p| !p.is_zombie())
+            .map(|p| p.pid)
+            .collect();
+        pids.sort();
+        pids
+    }
+
+    /// Terminate escaped processes, and kill those still alive after `grace`.
+    /// Return ids of killed processes.
+    pub fn terminate_escaped(&self, grace: Duration) -> Result<Vec<u32>> {
+        self.update()?;
+        self.terminate_gracefully(grace, Self::escaped)
+    }
+
+    /// Kill escaped processes immediately. Return ids of killed processes.
+    pub fn kill_escaped(&self) -> Result<Vec<u32>> {
+        self.update()?;
+        let mut killed = vec![];
+        for pid in self.escaped() {
+            if signal_process(pid, libc::SIGKILL)? {
+                killed.push(pid);
+            }
+        }
+
+        Ok(killed)
+    }
+
+    fn terminate_gracefully(
+        &self,
+        grace: Duration,
+        targets: fn(&Self) -> Vec<u32>,
+    ) -> Result<Vec<u32>> {
+        for pid in targets(self) {
+            signal_process(pid, libc::SIGTERM)?;
+        }
+
+        let start = Instant::now();
+        loop {
+            self.update()?;
+            if targets(self).is_empty() {
+                return Ok(vec![]);
+            }
+            if start.elapsed() >= grace {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        let mut killed = vec![];
+        for pid in targets(self) {
+            if signal_process(pid, libc::SIGKILL)? {
+                killed.push(pid);
+            }
+        }
+        if !killed.is_empty() {
+            warn!("Force killed descendants after {:?}: {:?}", grace, killed);
+        }
+        // reap the killed orphans
+        std::thread::sleep(Duration::from_millis(100));
+        self.update()?;
+
+        Ok(killed)
+    }
+
+    /// Track descendants in background until the session leader exits, then
+    /// terminate those left behind. The returned receiver resolves when done.
+    pub fn watch(self, grace: Duration) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            loop {
+                match self.update() {
+                    Ok(true) => std::thread::sleep(Duration::from_millis(100)),
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("Failed to track descendants: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            let left = self.alive();
+            if !left.is_empty() {
+                warn!(
+                    "Terminate processes left behind by session {}: {:?}",
+                    self.leader, left
+                );
+                if let Err(e) = self.terminate_gracefully(grace, Self::alive) {
+                    error!("Failed to terminate descendants: {}", e);
+                }
+            }
+            let _ = tx.send(());
+        });
+
+        rx
+    }
+}
+// reaper:1 ends here
+
+// usage
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*usage][usage:1]]
+use futures::sync::oneshot;
+use std::collections::HashSet;
+use std::process::ExitStatus;
+
+/// Resource usage of processes in a session.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// User CPU time in seconds
+    pub user_time: f64,
+    /// System CPU time in seconds
+    pub sys_time: f64,
+    /// Peak resident set size in kilobytes
+    pub peak_rss: u64,
+    /// Peak virtual memory size of a single process in kilobytes, which is
+    /// only sampled
+    #[serde(default)]
+    pub peak_vm: u64,
+    /// Number of processes found in the session
+    pub nprocs: usize,
+}
+
+/// Collect resource usage of a session by sampling `/proc`.
+#[derive(Debug)]
+struct SessionSampler {
+    sid: u32,
+    /// peak of total RSS of the session in kB
+    peak_rss: u64,
+    /// peak of virtual memory size of any process in the session in kB
+    peak_vm: u64,
+    /// processes ever seen in the session
+    pids: HashSet<u32>,
+    /// latest user and system CPU time in clock ticks of each process
+    cpu_ticks: std::collections::HashMap<u32, (u64, u64)>,
+}
+
+impl SessionSampler {
+    fn new(sid: u32) -> Self {
+        Self {
+            sid,
+            peak_rss: 0,
+            peak_vm: 0,
+            pids: HashSet::new(),
+            cpu_ticks: std::collections::HashMap::new(),
+        }
+    }
+
+    fn sample(&mut self) {
+        if let Ok(procs) = all_processes() {
+            let members: Vec<_> = procs
+                .iter()
+                .filter(|p| p.session == self.sid && !p.is_zombie())
+                .collect();
+            let rss = members.iter().map(|p| p.rss_kb()).sum();
+            self.peak_rss = self.peak_rss.max(rss);
+            // the limit on virtual memory applies to each process
+            let vm = members.iter().filter_map(|p| vm_peak_kb(p.pid)).max();
+            self.peak_vm = self.peak_vm.max(vm.unwrap_or(0));
+            self.pids.extend(members.iter().map(|p| p.pid));
+            for p in members {
+                self.cpu_ticks.insert(p.pid, (p.utime, p.stime));
+            }
+        }
+    }
+
+    /// Estimate resource usage from samples, for sessions that we cannot wait
+    /// for.
+    fn usage(&self) -> ResourceUsage {
+        let clk_tck = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+        let (utime, stime) = self
+            .cpu_ticks
+            .values()
+            .fold((0, 0), |(u, s), &(pu, ps)| (u + pu, s + ps));
+
+        ResourceUsage {
+            user_time: utime as f64 / clk_tck,
+            sys_time: stime as f64 / clk_tck,
+            peak_rss: self.peak_rss,
+            peak_vm: self.peak_vm,
+            nprocs: self.pids.len(),
+        }
+    }
+}
+
+/// Read peak virtual memory size in kilobytes of process `pid` from
+/// `/proc/[pid]/status`.
+fn vm_peak_kb(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find(|line| line.starts_with("VmPeak:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// A child process running in its own session. As a future, it resolves to
+/// exit status and resource usage of the child.
+#[derive(Debug)]
+pub struct SessionChild {
+    pid: u32,
+    stdout: Option<process::ChildStdout>,
+    stderr: Option<process::ChildStderr>,
+    rx: oneshot::Receiver<std::io::Result<(ExitStatus, ResourceUsage)>>,
+}
+
+impl SessionChild {
+    /// Spawn `cmd` and watch the child process in a background thread.
+    pub fn spawn(cmd: &mut process::Command) -> std::io::Result<Self> {
+        let mut child = cmd.spawn()?;
+        let pid = child.id();
+
+        let (tx, rx) = oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(watch_child(pid));
+        });
+
+        Ok(Self {
+            pid,
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            rx,
+        })
+    }
+
+    /// Take the handle to stdout of the child, if captured.
+    pub fn take_stdout(&mut self) -> Option<process::ChildStdout> {
+        self.stdout.take()
+    }
+
+    /// Take the handle to stderr of the child, if captured.
+    pub fn take_stderr(&mut self) -> Option<process::ChildStderr> {
+        self.stderr.take()
+    }
+
+    /// Return process id of the child, which is also the session id.
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+}
+
+impl Future for SessionChild {
+    type Item = (ExitStatus, ResourceUsage);
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.rx.poll() {
+            Ok(Async::Ready(r)) => r.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "child watcher has gone",
+            )),
+        }
+    }
+}
+
+/// Interval for sampling session processes from `/proc`.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait for child `pid` to exit, sampling its session meanwhile.
+fn watch_child(pid: u32) -> std::io::Result<(ExitStatus, ResourceUsage)> {
+    let mut sampler = SessionSampler::new(pid);
+    let mut last_sample: Option<Instant> = None;
+    loop {
+        if let Some((status, rusage)) = try_wait4(pid)? {
+            let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 * 1e-6;
+            let usage = ResourceUsage {
+                user_time: seconds(rusage.ru_utime),
+                sys_time: seconds(rusage.ru_stime),
+                // ru_maxrss is in kilobytes on Linux
+                peak_rss: sampler.peak_rss.max(rusage.ru_maxrss as u64),
+                peak_vm: sampler.peak_vm,
+                nprocs: sampler.pids.len().max(1),
+            };
+            return Ok((status, usage));
+        }
+
+        if last_sample.map_or(true, |t| t.elapsed() >= SAMPLE_INTERVAL) {
+            sampler.sample();
+            last_sample = Some(Instant::now());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Watch session `sid` not spawned by us. The returned future resolves to
+/// resource usage of the session when all processes in it exit.
+pub fn watch_session(sid: u32) -> impl Future<Item = ResourceUsage, Error = Error> + Send {
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let mut sampler = SessionSampler::new(sid);
+        let mut last_sample: Option<Instant> = None;
+        loop {
+            match alive_session_members(sid) {
+                Ok(ref pids) if pids.is_empty() => break,
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            }
+            if last_sample.map_or(true, |t| t.elapsed() >= SAMPLE_INTERVAL) {
+                sampler.sample();
+                last_sample = Some(Instant::now());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        let _ = tx.send(Ok(sampler.usage()));
+    });
+
+    rx.map_err(|_| format_err!("session watcher has gone"))
+        .and_then(|r| r)
+}
+
+/// Check if child `pid` has exited without blocking. Return its exit status
+/// and resource usage if so.
+fn try_wait4(pid: u32) -> std::io::Result<Option<(ExitStatus, libc::rusage)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let rc = unsafe { libc::wait4(pid as libc::pid_t, &mut status, libc::WNOHANG, &mut rusage) };
+    match rc {
+        0 => Ok(None),
+        rc if rc < 0 => {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                Ok(None)
+            } else {
+                Err(e)
+            }
+        }
+        _ => Ok(Some((ExitStatus::from_raw(status), rusage))),
+    }
+}
+// usage:1 ends here
+
+// test
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*test][test:1]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration(" 45s "), Ok(45));
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert_eq!(parse_duration("2h30m"), Ok(9000));
+        assert_eq!(parse_duration("1d12h"), Ok(129600));
+        assert_eq!(parse_duration("1h1h"), Ok(7200));
+
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("2x").is_err());
+        assert!(parse_duration("2h30").is_err());
+        assert!(parse_duration("-1s").is_err());
+
+        // overflow
+        assert!(parse_duration("18446744073709551615s").is_ok());
+        assert!(parse_duration("18446744073709551615m").is_err());
+        assert!(parse_duration("18446744073709551615s1s").is_err());
+        assert!(parse_duration("999999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn test_retry() {
+        let attempt = |n, exit_code, signal, termination| Attempt {
+            attempt: n,
+            start_time: String::new(),
+            end_time: String::new(),
+            wall_time: 0.0,
+            exit_code,
+            signal,
+            termination,
+            usage: ResourceUsage::default(),
+        };
+        let failed = |n| attempt(n, Some(1), None, Termination::Normal);
+        let crashed = |n| attempt(n, None, Some(libc::SIGSEGV), Termination::Normal);
+
+        // retry any failure up to the number of retries
+        let runner = Runner::new("true").with_retries(2);
+        assert!(runner.should_retry(&failed(1)));
+        assert!(runner.should_retry(&failed(2)));
+        assert!(!runner.should_retry(&failed(3)));
+        assert!(runner.should_retry(&crashed(1)));
+        assert!(!runner.should_retry(&attempt(1, Some(0), None, Termination::Normal)));
+        assert!(!runner.should_retry(&attempt(1, Some(1), None, Termination::Timeout)));
+        assert!(!runner.should_retry(&attempt(1, None, None, Termination::Interrupted)));
+        assert!(!Runner::new("true").should_retry(&failed(1)));
+
+        // retry only listed exit codes
+        let runner = runner.with_retry_on_exit_code(3);
+        assert!(runner.should_retry(&attempt(1, Some(3), None, Termination::Normal)));
+        assert!(!runner.should_retry(&failed(1)));
+        assert!(!runner.should_retry(&crashed(1)));
+        assert!(!runner.should_retry(&attempt(1, Some(0), None, Termination::Normal)));
+        assert!(!runner.should_retry(&attempt(3, Some(3), None, Termination::Normal)));
+
+        // exponential backoff
+        let runner = Runner::new("true").with_retry_delay(10);
+        assert_eq!(runner.backoff(1), Duration::from_secs(10));
+        assert_eq!(runner.backoff(2), Duration::from_secs(20));
+        assert_eq!(runner.backoff(4), Duration::from_secs(80));
+        assert_eq!(runner.backoff(100), Duration::from_secs(u64::max_value()));
+    }
+
+    #[test]
+    fn test_parse_deadline() {
+        use chrono::{NaiveDateTime, Timelike};
+
+        let naive = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let t = parse_deadline("2019-07-26 08:00").unwrap();
+        assert_eq!(t.naive_local(), naive("2019-07-26 08:00:00"));
+        let t = parse_deadline("2019-07-26T08:00:30").unwrap();
+        assert_eq!(t.naive_local(), naive("2019-07-26 08:00:30"));
+        let t = parse_deadline("2019-07-26T08:00:00+00:00").unwrap();
+        assert_eq!(t.timestamp(), 1564128000);
+
+        // time of day already passed rolls over to the next day
+        let now = Local::now().with_nanosecond(0).unwrap();
+        let past = now - chrono::Duration::minutes(2);
+        let t = parse_deadline(&past.format("%H:%M:%S").to_string()).unwrap();
+        assert_eq!(
+            t.naive_local(),
+            past.naive_local() + chrono::Duration::days(1)
+        );
+
+        // time of day to come is the next occurrence
+        let future = now + chrono::Duration::minutes(2);
+        let t = parse_deadline(&future.format("%H:%M:%S").to_string()).unwrap();
+        assert_eq!(t.naive_local(), future.naive_local());
+
+        let t = parse_deadline("08:00").unwrap();
+        assert!(t > Local::now());
+        assert!(t - Local::now() <= chrono::Duration::days(1));
+        assert_eq!((t.hour(), t.minute(), t.second()), (8, 0, 0));
+
+        assert!(parse_deadline("tomorrow").is_err());
+        assert!(parse_deadline("25:00").is_err());
+    }
+
+    #[test]
+    fn test_limits_exceeded_by() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let killed = |signal| ExitStatus::from_raw(signal);
+        let usage = |cpu_time, peak_vm| ResourceUsage {
+            user_time: cpu_time,
+            peak_vm,
+            ..ResourceUsage::default()
+        };
+        let limits = ResourceLimits {
+            max_memory: Some(1024),
+            max_cpu_time: Some(10),
+            ..ResourceLimits::default()
+        };
+
+        // a plain crash is not a limit kill
+        assert!(!limits.exceeded_by(&killed(libc::SIGSEGV), &usage(1.0, 1024)));
+        assert!(limits.exceeded_by(&killed(libc::SIGSEGV), &usage(1.0, 1000 * 1024)));
+        assert!(limits.exceeded_by(&killed(libc::SIGABRT), &usage(1.0, 1024 * 1024)));
+        assert!(!limits.exceeded_by(&killed(libc::SIGKILL), &usage(1.0, 1024 * 1024)));
+        assert!(limits.exceeded_by(&killed(libc::SIGKILL), &usage(10.0, 0)));
+        assert!(limits.exceeded_by(&killed(libc::SIGXCPU), &usage(1.0, 0)));
+        assert!(!limits.exceeded_by(&killed(libc::SIGXFSZ), &usage(1.0, 0)));
+        assert!(!limits.exceeded_by(&ExitStatus::from_raw(1 << 8), &usage(10.0, 0)));
+
+        let unlimited = ResourceLimits::default();
+        assert!(!unlimited.exceeded_by(&killed(libc::SIGSEGV), &usage(1.0, 1024 * 1024)));
+        assert!(!unlimited.exceeded_by(&killed(libc::SIGKILL), &usage(10.0, 0)));
+
+        assert!(vm_peak_kb(process::id()).unwrap() > 0);
+        assert_eq!(parse_megabytes("1024"), Ok(1024));
+        assert!(parse_megabytes("18446744073709551615").is_err());
+        assert!(parse_megabytes("-1").is_err());
+
+        let limits: ResourceLimits = serde_json::from_str(r#"{"max_memory": 1024}"#).unwrap();
+        assert_eq!(limits.max_memory, Some(1024));
+        assert_eq!(limits.max_file_size, None);
+        let json = r#"{"max_memory": 18446744073709551615}"#;
+        assert!(serde_json::from_str::<ResourceLimits>(json).is_err());
+    }
+
+    #[test]
+    fn test_process_stat_parse() {
+        let line = "1234 (my prog) (x)) S 1 1234 1234 0 -1 4194560 100 0 0 0 7 3 0 0 20 0 1 0 \
+                    12345 1000000 256 18446744073709551615 1 1 0 0 0 0 0 0 0 0 0 0 17 3";
+        let stat = ProcessStat::parse(line).unwrap();
+        assert_eq!(stat.pid, 1234);
+        assert_eq!(stat.comm, "my prog) (x)");
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.ppid, 1);
+        assert_eq!(stat.pgrp, 1234);
+        assert_eq!(stat.session, 1234);
+        assert_eq!(stat.utime, 7);
+        assert_eq!(stat.stime, 3);
+        assert_eq!(stat.rss, 256);
+        assert!(!stat.is_zombie());
+
+        assert!(ProcessStat::parse("").is_none());
+        assert!(ProcessStat::parse("1234 (prog) S 1 2").is_none());
+        assert!(ProcessStat::parse("x (prog) S 1 1234 1234").is_none());
+
+        let stat = ProcessStat::from_pid(process::id()).unwrap();
+        assert_eq!(stat.pid, process::id());
+        assert_eq!(stat.session, unsafe { libc::getsid(0) } as u32);
+    }
+}
+// test:1 ends here
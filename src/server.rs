@@ -2,13 +2,17 @@
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*imports][imports:1]]
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Local};
+use futures::sync::oneshot;
 use serde::{Deserialize, Serialize};
 use tempfile::{tempdir, tempdir_in, TempDir};
 use tokio::prelude::*;
 
 use quicli::prelude::*;
 //use crate::common::*;
+use crate::local::{ResourceLimits, ResourceUsage, SessionChild, TimeoutHook};
 // imports:1 ends here
 
 // base
@@ -20,13 +24,17 @@ pub const DEFAULT_SERVER_ADDRESS: &str = "127.0.0.1:3030";
 // job
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*job][job:1]]
-#[derive(Clone, Debug)]
-enum JobStatus {
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
     NotStarted,
     Running,
-    /// failure code
+    /// failure code: the exit code, or 128+signal if killed by a signal, or
+    /// -1 if the command was lost
     Failure(i32),
     Success,
+    /// the job will never start, as its dependencies cannot be satisfied
+    Cancelled,
 }
 
 impl Default for JobStatus {
@@ -37,6 +45,29 @@ impl Default for JobStatus {
 
 pub type JobId = usize;
 
+/// Status of a job reported by the server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub state: JobStatus,
+    /// Position in the queue counted from 1, if the job is waiting for a
+    /// free slot
+    pub queue_position: Option<usize>,
+    pub priority: i32,
+    /// Exit code of the command
+    pub exit_code: Option<i32>,
+    /// Signal that killed the command
+    pub signal: Option<i32>,
+    /// When the job was submitted
+    pub created: Option<String>,
+    /// When the command started
+    pub started: Option<String>,
+    /// When the command exited
+    pub finished: Option<String>,
+    /// Why the job was cancelled
+    pub reason: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Job {
     out_file: String,
@@ -51,15 +82,82 @@ pub struct Job {
 
     inp_file: String,
 
+    /// Resource limits for the job
+    #[serde(default)]
+    limits: ResourceLimits,
+
+    /// Job timeout in seconds, or in human-friendly form such as "2h30m"
+    #[serde(default, deserialize_with = "crate::local::deserialize_duration")]
+    timeout: Option<u64>,
+
+    /// Terminate the job at this time, such as "2019-07-26 08:00"
+    #[serde(default, deserialize_with = "crate::local::deserialize_deadline")]
+    deadline: Option<DateTime<Local>>,
+
+    /// Action to take shortly before the job is killed on timeout
+    #[serde(default)]
+    on_timeout: TimeoutHook,
+
+    /// Queued jobs with higher priority start first
+    #[serde(default)]
+    priority: i32,
+
+    /// Start only after all these jobs succeed
+    #[serde(default)]
+    after_ok: Vec<JobId>,
+
+    /// Start only after all these jobs finish, whether succeeded or not
+    #[serde(default)]
+    after_any: Vec<JobId>,
+
+    /// Start only after all these jobs fail or are cancelled
+    #[serde(default)]
+    after_fail: Vec<JobId>,
+
     #[serde(skip)]
     status: JobStatus,
 
     #[serde(skip)]
     wrk_dir: Option<tempfile::TempDir>,
 
-    // command session
+    // id of the command session
+    #[serde(skip)]
+    session: Option<u32>,
+
+    #[serde(skip)]
+    exit_code: Option<i32>,
+
+    #[serde(skip)]
+    signal: Option<i32>,
+
+    // timestamps of job status changes
+    #[serde(skip)]
+    created: Option<String>,
+
+    #[serde(skip)]
+    started: Option<String>,
+
+    #[serde(skip)]
+    finished: Option<String>,
+
+    // why the job was cancelled
+    #[serde(skip)]
+    reason: Option<String>,
+
+    // submission order for scheduling
+    #[serde(skip)]
+    seq: usize,
+
+    // notifier for waiters of job completion
+    #[serde(skip)]
+    done: Option<future::Shared<oneshot::Receiver<()>>>,
+
     #[serde(skip)]
-    session: Option<tokio_process::Child>,
+    done_tx: Option<oneshot::Sender<()>>,
+
+    // resource usage of finished command session
+    #[serde(skip)]
+    usage: Option<ResourceUsage>,
 }
 
 impl Job {
@@ -79,14 +177,80 @@ impl Job {
             err_file: "job.err".into(),
             run_file: "run".into(),
             inp_file: "job.inp".into(),
+            limits: ResourceLimits::default(),
+            timeout: None,
+            deadline: None,
+            on_timeout: TimeoutHook::default(),
+            priority: 0,
+            after_ok: vec![],
+            after_any: vec![],
+            after_fail: vec![],
 
             // state variables
             status: JobStatus::default(),
             session: None,
             wrk_dir: None,
+            usage: None,
+            exit_code: None,
+            signal: None,
+            created: None,
+            started: None,
+            finished: None,
+            reason: None,
+            seq: 0,
+            done: None,
+            done_tx: None,
         }
     }
 
+    /// Set resource limits for the job.
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Set job timeout in seconds.
+    pub fn with_timeout(mut self, t: u64) -> Self {
+        self.timeout = Some(t);
+        self
+    }
+
+    /// Set deadline when the job will be terminated.
+    pub fn with_deadline(mut self, deadline: DateTime<Local>) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set the hook to run shortly before the job is killed on timeout.
+    pub fn with_timeout_hook(mut self, hook: TimeoutHook) -> Self {
+        self.on_timeout = hook;
+        self
+    }
+
+    /// Set job priority. Queued jobs with higher priority start first.
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Start the job only after job `id` succeeds.
+    pub fn with_after_ok(mut self, id: JobId) -> Self {
+        self.after_ok.push(id);
+        self
+    }
+
+    /// Start the job only after job `id` finishes, whether succeeded or not.
+    pub fn with_after_any(mut self, id: JobId) -> Self {
+        self.after_any.push(id);
+        self
+    }
+
+    /// Start the job only after job `id` fails or is cancelled.
+    pub fn with_after_fail(mut self, id: JobId) -> Self {
+        self.after_fail.push(id);
+        self
+    }
+
     /// Set content of job stdin stream.
     fn with_stdin(mut self, content: &str) -> Self {
         self.input = content.into();
@@ -117,6 +281,41 @@ impl Job {
         wdir.join(&self.run_file)
     }
 
+    /// Return current status of the job with `id`.
+    fn info(&self, id: JobId, queue_position: Option<usize>) -> JobInfo {
+        JobInfo {
+            id,
+            state: self.status,
+            queue_position,
+            priority: self.priority,
+            exit_code: self.exit_code,
+            signal: self.signal,
+            created: self.created.clone(),
+            started: self.started.clone(),
+            finished: self.finished.clone(),
+            reason: self.reason.clone(),
+        }
+    }
+
+    /// Return a future resolving when the job finishes. It fails if the job
+    /// is deleted before that.
+    fn completion(&mut self) -> future::Shared<oneshot::Receiver<()>> {
+        if self.done.is_none() {
+            let (tx, rx) = oneshot::channel();
+            self.done_tx = Some(tx);
+            self.done = Some(rx.shared());
+        }
+        self.done.clone().unwrap()
+    }
+
+    /// Test if the command has exited.
+    fn is_finished(&self) -> bool {
+        match self.status {
+            JobStatus::NotStarted | JobStatus::Running => false,
+            _ => true,
+        }
+    }
+
     fn wrk_dir(&self) -> &Path {
         if let Some(d) = &self.wrk_dir {
             d.path()
@@ -134,12 +333,16 @@ impl Job {
     /// Create runnable script file and stdin file from self.script and
     /// self.input.
     fn build(&mut self) {
-        use std::fs::File;
-        use std::os::unix::fs::OpenOptionsExt;
-
         // create working directory in scratch space.
         let wdir = tempfile::tempdir().expect("temp dir");
         self.wrk_dir = Some(wdir);
+        self.write_files();
+    }
+
+    /// Write script file and stdin file into working directory.
+    fn write_files(&self) {
+        use std::fs::File;
+        use std::os::unix::fs::OpenOptionsExt;
 
         // create run file
         let file = self.run_file();
@@ -148,6 +351,7 @@ impl Job {
         match std::fs::OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(true)
             .mode(0o770)
             .open(&file)
         {
@@ -171,77 +375,165 @@ impl Job {
         }
     }
 
-    /// Run command in background.
-    fn start(&mut self) {
-        use crate::local::Runner;
+    /// Replace definition of a queued job with `update`, keeping its state
+    /// in the server.
+    fn update(&mut self, mut update: Job) {
+        use std::mem::take;
+
+        // Job implements Drop, so fields could not be moved out of `update`
+        self.out_file = take(&mut update.out_file);
+        self.err_file = take(&mut update.err_file);
+        self.run_file = take(&mut update.run_file);
+        self.script = take(&mut update.script);
+        self.input = take(&mut update.input);
+        self.inp_file = take(&mut update.inp_file);
+        self.limits = take(&mut update.limits);
+        self.timeout = update.timeout;
+        self.deadline = update.deadline;
+        self.on_timeout = take(&mut update.on_timeout);
+        self.priority = update.priority;
+        self.after_ok = take(&mut update.after_ok);
+        self.after_any = take(&mut update.after_any);
+        self.after_fail = take(&mut update.after_fail);
+        self.write_files();
+    }
 
-        use tokio::prelude::*;
-        use tokio_process::CommandExt;
+    /// Run command in background. Return the command session to be watched
+    /// for completion.
+    fn start(&mut self) -> crate::common::Result<SessionChild> {
+        use crate::local::Runner;
 
         let wdir = self.wrk_dir();
         info!("job work direcotry: {}", wdir.display());
 
-        let runner = Runner::new(&self.run_file());
-        let mut child = runner
-            .build_command()
-            .current_dir(wdir)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn_async()
-            .expect("spawn command session");
-
-        let mut stdin = child
-            .stdin()
-            .take()
-            .expect("child did not have a handle to stdout");
-        let stdout = child
-            .stdout()
-            .take()
-            .expect("child did not have a handle to stdout");
-        let stderr = child
-            .stderr()
-            .take()
-            .expect("child did not have a handle to stderr");
-
-        // NOTE: suppose stdin stream is small.
-        stdin.write_all(self.input.as_bytes()).expect("write stdin");
-
         // redirect stdout and stderr to files for user inspection.
-        let save_stdout = tokio::fs::File::create(self.out_file())
-            .and_then(move |f| tokio::io::copy(stdout, f))
-            .map(move |_| ())
-            .map_err(|e| panic!("error while saving stdout: {}", e));
-        let save_stderr = tokio::fs::File::create(self.err_file())
-            .and_then(move |f| tokio::io::copy(stderr, f))
-            .map(|_| ())
-            .map_err(|e| panic!("error while saving stderr: {}", e));
-
-        tokio::spawn(save_stderr);
-        tokio::spawn(save_stdout);
+        let runner = Runner::new(&self.run_file())
+            .with_limits(self.limits.clone())
+            .with_workdir(wdir)
+            .with_stdin(self.inp_file())
+            .with_stdout(self.out_file())
+            .with_stderr(self.err_file());
+        let mut cmd = runner.build_command()?;
+        let child = SessionChild::spawn(&mut cmd)?;
 
         let sid = child.id();
         info!("command running in session {}", sid);
-        self.session = Some(child);
+        self.session = Some(sid);
+        self.status = JobStatus::Running;
+        self.started = Some(crate::common::timestamp_now());
+
+        if let Some(t) = crate::local::wall_time_limit(self.timeout, self.deadline) {
+            self.watch_timeout(sid, t);
+        }
+
+        Ok(child)
+    }
+
+    /// Terminate command session `sid` on timeout in background, with the
+    /// on-timeout hook taken before that. Both are cancelled once the job
+    /// finishes, so we will never signal a reused process id.
+    fn watch_timeout(&mut self, sid: u32, timeout: Duration) {
+        use crate::local::{session_members, terminate_session_gracefully, DEFAULT_KILL_AFTER};
+
+        let done = self.completion();
+        let deadline = Instant::now() + timeout;
+        if self.on_timeout.action.is_some() {
+            let wdir = self.wrk_dir().to_owned();
+            let hook = self.on_timeout.schedule(sid, move || Some(deadline), wdir);
+            tokio::spawn(hook.select2(done.clone()).then(|_| Ok(())));
+        }
+
+        let grace = Duration::from_secs(DEFAULT_KILL_AFTER);
+        let kill = tokio::timer::Delay::new(deadline)
+            .map_err(|e| error!("job timer error: {}", e))
+            .map(move |_| {
+                // the session could have gone
+                match session_members(sid) {
+                    Ok(ref pids) if !pids.is_empty() => {}
+                    _ => return,
+                }
+                warn!("Job session {} timeout!", sid);
+                // escalate in background, so we will not block the event loop
+                std::thread::spawn(move || {
+                    if let Err(e) = terminate_session_gracefully(sid, grace) {
+                        error!("Failed to terminate job session {}: {}", sid, e);
+                    }
+                });
+            });
+        tokio::spawn(kill.select2(done).then(|_| Ok(())));
     }
 
     /// Terminate background command session.
     fn terminate(&mut self) {
-        if let Some(child) = &mut self.session {
-            let sid = child.id();
-            crate::local::terminate_session(sid).expect("term session");
+        use crate::local::{kill_session_after, terminate_session, DEFAULT_KILL_AFTER};
+
+        if self.is_finished() {
+            debug!("Job has finished.");
+        } else if let Some(sid) = self.session {
+            if let Err(e) = terminate_session(sid) {
+                error!("Failed to terminate job session {}: {}", sid, e);
+            }
+            // the job database could be locked by the caller, so escalate in
+            // background
+            let grace = std::time::Duration::from_secs(DEFAULT_KILL_AFTER);
+            std::thread::spawn(move || {
+                if let Err(e) = kill_session_after(sid, grace) {
+                    error!("Failed to kill job session {}: {}", sid, e);
+                }
+            });
             info!("Job with command session {} has been terminated.", sid);
         } else {
             debug!("Job not started yet.");
         }
     }
 
-    /// Wait for background command to complete.
-    fn wait(&mut self) {
-        if let Some(mut child) = self.session.take() {
-            child.wait_with_output().wait();
-        } else {
-            error!("Job not started yet.");
+    /// Record the outcome of the command when it exits.
+    fn finish(&mut self, result: std::io::Result<(std::process::ExitStatus, ResourceUsage)>) {
+        use std::os::unix::process::ExitStatusExt;
+
+        self.finished = Some(crate::common::timestamp_now());
+        match result {
+            Ok((status, usage)) => {
+                info!("Job exited with {:?}, resource usage: {:?}", status, usage);
+                self.exit_code = status.code();
+                self.signal = status.signal();
+                self.status = match (status.code(), status.signal()) {
+                    (Some(0), _) => JobStatus::Success,
+                    (Some(code), _) => JobStatus::Failure(code),
+                    (None, Some(sig)) => JobStatus::Failure(128 + sig),
+                    (None, None) => JobStatus::Failure(-1),
+                };
+                self.usage = Some(usage);
+            }
+            Err(e) => {
+                error!("Error while waiting for job: {}", e);
+                self.status = JobStatus::Failure(-1);
+            }
+        }
+
+        self.notify();
+    }
+
+    /// Mark a job that could not be started as failed for `reason`.
+    fn fail(&mut self, reason: String) {
+        self.finished = Some(crate::common::timestamp_now());
+        self.status = JobStatus::Failure(-1);
+        self.reason = Some(reason);
+        self.notify();
+    }
+
+    /// Cancel a queued job for `reason`.
+    fn cancel(&mut self, reason: String) {
+        self.finished = Some(crate::common::timestamp_now());
+        self.status = JobStatus::Cancelled;
+        self.reason = Some(reason);
+        self.notify();
+    }
+
+    /// Wake up all waiters of job completion.
+    fn notify(&mut self) {
+        if let Some(tx) = self.done_tx.take() {
+            let _ = tx.send(());
         }
     }
 }
@@ -276,10 +568,14 @@ type Db = Arc<Mutex<Jobs>>;
 /// Computation server.
 pub struct Server {
     address: SocketAddr,
+    /// max number of jobs running at the same time
+    slots: usize,
 }
 
 impl Server {
-    fn new(addr: &str) -> Self {
+    /// Create a server binding to `addr`, running as many jobs as online CPUs
+    /// at the same time.
+    pub fn new(addr: &str) -> Self {
         let addrs: Vec<_> = addr.to_socket_addrs().expect("bad address").collect();
 
         dbg!(&addrs);
@@ -287,7 +583,10 @@ impl Server {
             0 => {
                 panic!("no valid server address!");
             }
-            1 => Self { address: addrs[0] },
+            1 => Self {
+                address: addrs[0],
+                slots: default_slots(),
+            },
             _ => {
                 let ipv4addrs: Vec<_> = addrs.iter().filter(|a| a.is_ipv4()).collect();
                 if ipv4addrs.len() == 0 {
@@ -296,43 +595,206 @@ impl Server {
                     warn!("found multiple IPV4 addresses: {:?}", ipv4addrs);
                     Self {
                         address: *ipv4addrs[0],
+                        slots: default_slots(),
                     }
                 }
             }
         }
     }
+
+    /// Run at most `n` jobs at the same time.
+    pub fn with_slots(mut self, n: usize) -> Self {
+        self.slots = n.max(1);
+        self
+    }
+}
+
+/// Return the number of online CPUs, which is the default number of jobs
+/// running at the same time.
+pub fn default_slots() -> usize {
+    let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if n > 0 {
+        n as usize
+    } else {
+        1
+    }
 }
 // base:1 ends here
 
+// scheduler
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*scheduler][scheduler:1]]
+/// Readiness of a queued job with respect to its dependencies.
+enum Readiness {
+    Ready,
+    Waiting,
+    Unsatisfiable(String),
+}
+
+/// Check if dependencies of `job` have finished in the required state.
+fn readiness(jobs: &Jobs, job: &Job) -> Readiness {
+    let deps = job
+        .after_ok
+        .iter()
+        .map(|&id| (id, "after_ok"))
+        .chain(job.after_any.iter().map(|&id| (id, "after_any")))
+        .chain(job.after_fail.iter().map(|&id| (id, "after_fail")));
+
+    let mut waiting = false;
+    for (id, kind) in deps {
+        // the id could be reused by a job submitted later
+        let dep = match jobs.get(id) {
+            Some(dep) if dep.seq < job.seq => dep,
+            _ => return Readiness::Unsatisfiable(format!("dependency job {} not found", id)),
+        };
+        match (kind, dep.status) {
+            (_, JobStatus::NotStarted) | (_, JobStatus::Running) => waiting = true,
+            ("after_any", _) => {}
+            ("after_ok", JobStatus::Success) => {}
+            ("after_fail", JobStatus::Failure(_)) | ("after_fail", JobStatus::Cancelled) => {}
+            (kind, status) => {
+                return Readiness::Unsatisfiable(format!(
+                    "dependency job {} ended as {:?}, which does not satisfy {}",
+                    id, status, kind
+                ));
+            }
+        }
+    }
+
+    if waiting {
+        Readiness::Waiting
+    } else {
+        Readiness::Ready
+    }
+}
+
+/// Return ids of queued jobs in the order to be started: by priority, and
+/// then by submission time. Jobs waiting for dependencies are excluded.
+fn queue(jobs: &Jobs) -> Vec<JobId> {
+    let mut queued: Vec<_> = jobs
+        .iter()
+        .filter(|(_, job)| job.status == JobStatus::NotStarted)
+        .filter(|(_, job)| match readiness(jobs, job) {
+            Readiness::Ready => true,
+            _ => false,
+        })
+        .map(|(id, job)| (std::cmp::Reverse(job.priority), job.seq, id))
+        .collect();
+    queued.sort();
+    queued.into_iter().map(|(_, _, id)| id).collect()
+}
+
+/// Return status of job `id` with its position in the queue.
+fn job_info(jobs: &Jobs, id: JobId) -> JobInfo {
+    let position = queue(jobs).iter().position(|&x| x == id).map(|i| i + 1);
+    jobs[id].info(id, position)
+}
+
+/// Start queued jobs in order while there are free slots.
+fn dispatch(jobs: &mut Jobs, db: &Db, slots: usize) {
+    // Jobs failed to start free their slots, and could affect their
+    // dependents, so we go over again.
+    loop {
+        cancel_unsatisfiable(jobs);
+
+        let running = jobs
+            .iter()
+            .filter(|(_, job)| job.status == JobStatus::Running)
+            .count();
+
+        let mut failed = false;
+        for id in queue(jobs).into_iter().take(slots.saturating_sub(running)) {
+            info!("start queued job {}", id);
+            match jobs[id].start() {
+                Ok(child) => watch_job(id, child, db.clone(), slots),
+                Err(e) => {
+                    error!("failed to start job {}: {}", id, e);
+                    jobs[id].fail(format!("failed to start: {}", e));
+                    failed = true;
+                }
+            }
+        }
+        if !failed {
+            break;
+        }
+    }
+}
+
+/// Cancel jobs whose dependencies can no longer be satisfied, which could
+/// make their dependents unsatisfiable in turn.
+fn cancel_unsatisfiable(jobs: &mut Jobs) {
+    loop {
+        let unsatisfiable: Vec<_> = jobs
+            .iter()
+            .filter(|(_, job)| job.status == JobStatus::NotStarted)
+            .filter_map(|(id, job)| match readiness(jobs, job) {
+                Readiness::Unsatisfiable(reason) => Some((id, reason)),
+                _ => None,
+            })
+            .collect();
+        if unsatisfiable.is_empty() {
+            break;
+        }
+        for (id, reason) in unsatisfiable {
+            warn!("cancel job {}: {}", id, reason);
+            jobs[id].cancel(reason);
+        }
+    }
+}
+// scheduler:1 ends here
+
 // create job
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*create%20job][create job:1]]
 /// POST /jobs with JSON body
-fn create_job(mut create: Job, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
+fn create_job(mut create: Job, db: Db, slots: usize) -> Result<impl warp::Reply, warp::Rejection> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT_SEQ: AtomicUsize = AtomicUsize::new(0);
+
     info!("create_job: {:?}", create);
     let mut jobs = db.lock().unwrap();
-
-    // run command
-    create.build();
-    create.start();
+    create.created = Some(crate::common::timestamp_now());
+    create.seq = NEXT_SEQ.fetch_add(1, Ordering::SeqCst);
 
     // Insert job into the queue.
+    create.build();
     let jid = jobs.insert(create);
 
+    // run command if there is a free slot
+    dispatch(&mut jobs, &db, slots);
+
     Ok(warp::reply::json(&jid))
 }
+
+/// Record the outcome of job `id` when its command session exits, and start
+/// the next queued job.
+fn watch_job(id: JobId, child: SessionChild, db: Db, slots: usize) {
+    let sid = child.id();
+    let done = child.then(move |result| {
+        let mut jobs = db.lock().unwrap();
+        // the job could have been deleted, and its id reused
+        if jobs.contains(id) && jobs[id].session == Some(sid) {
+            jobs[id].finish(result);
+        }
+        dispatch(&mut jobs, &db, slots);
+        Ok(())
+    });
+    tokio::spawn(done);
+}
 // create job:1 ends here
 
 // delete job
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*delete%20job][delete job:1]]
 /// DELETE /jobs/:id
-fn delete_job(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
+fn delete_job(id: JobId, db: Db, slots: usize) -> Result<impl warp::Reply, warp::Rejection> {
     info!("delete_job: id={}", id);
     let mut jobs = db.lock().unwrap();
 
     if jobs.contains(id) {
         let _ = jobs.remove(id);
+        // the slot of a running job is free now
+        dispatch(&mut jobs, &db, slots);
 
         // respond with a `204 No Content`, which means successful,
         // yet no body expected...
@@ -349,19 +811,59 @@ fn delete_job(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*update%20job][update job:1]]
 /// PUT /jobs/:id with JSON body
-fn update_job(id: JobId, update: Job, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
+///
+/// Only jobs not started yet can be updated, e.g. to change the priority.
+fn update_job(
+    id: JobId,
+    update: Job,
+    db: Db,
+    slots: usize,
+) -> Result<impl warp::Reply, warp::Rejection> {
     debug!("update_job: id={}, job={:?}", id, update);
     let mut jobs = db.lock().unwrap();
 
     // Look for the specified Job...
-    if jobs.contains(id) {
-        jobs[id] = update;
-        return Ok(warp::reply());
+    match jobs.get_mut(id) {
+        Some(job) if job.status == JobStatus::NotStarted => {
+            job.update(update);
+            // the job could be ready to start with updated dependencies
+            dispatch(&mut jobs, &db, slots);
+            Ok(warp::reply())
+        }
+        Some(_) => {
+            debug!("    -> job has started!");
+            Err(warp::reject::custom(format!("job {} has started", id)))
+        }
+        None => {
+            debug!("    -> job id not found!");
+            Err(warp::reject::not_found())
+        }
     }
+}
 
-    // If the for loop didn't return OK, then the ID doesn't exist...
-    debug!("    -> job id not found!");
-    Err(warp::reject::not_found())
+/// PUT /jobs/:id/priority with JSON body
+fn update_job_priority(
+    id: JobId,
+    priority: i32,
+    db: Db,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    debug!("update_job_priority: id={}, priority={}", id, priority);
+    let mut jobs = db.lock().unwrap();
+
+    match jobs.get_mut(id) {
+        Some(job) if job.status == JobStatus::NotStarted => {
+            job.priority = priority;
+            Ok(warp::reply())
+        }
+        Some(_) => {
+            debug!("    -> job has started!");
+            Err(warp::reject::custom(format!("job {} has started", id)))
+        }
+        None => {
+            debug!("    -> job id not found!");
+            Err(warp::reject::not_found())
+        }
+    }
 }
 // update job:1 ends here
 
@@ -374,7 +876,7 @@ fn update_job(id: JobId, update: Job, db: Db) -> Result<impl warp::Reply, warp::
 fn list_jobs(db: Db) -> impl warp::Reply {
     info!("list jobs");
     let jobs = db.lock().unwrap();
-    let list: Vec<JobId> = jobs.iter().map(|(k, _)| k).collect();
+    let list: Vec<JobInfo> = jobs.iter().map(|(k, _)| job_info(&jobs, k)).collect();
     warp::reply::json(&list)
 }
 
@@ -403,6 +905,46 @@ fn list_job_files(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection
         Err(warp::reject::not_found())
     }
 }
+
+/// List processes of a job
+///
+/// GET /jobs/:id/processes
+fn list_job_processes(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
+    use crate::local::ProcessTree;
+
+    let jobs = db.lock().unwrap();
+    info!("list processes for job {}", id);
+
+    if jobs.contains(id) {
+        let list = match jobs[id].session {
+            Some(sid) => {
+                let tree = ProcessTree::snapshot().map_err(|e| {
+                    error!("{}", e);
+                    warp::reject::custom(e.to_string())
+                })?;
+                tree.session(sid)
+            }
+            None => vec![],
+        };
+        Ok(warp::reply::json(&list))
+    } else {
+        Err(warp::reject::not_found())
+    }
+}
+
+/// Show status of a job
+///
+/// GET /jobs/:id/status
+fn job_status(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
+    let jobs = db.lock().unwrap();
+    info!("job status: id={}", id);
+
+    if jobs.contains(id) {
+        Ok(warp::reply::json(&job_info(&jobs, id)))
+    } else {
+        Err(warp::reject::not_found())
+    }
+}
 // list job:1 ends here
 
 // job files
@@ -495,20 +1037,38 @@ pub fn send_signal(signal: libc::c_int) {
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*wait%20job][wait job:1]]
 /// GET /jobs/:id
-fn wait_job(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
+///
+/// Respond when the job finishes, without blocking other requests.
+fn wait_job(
+    id: JobId,
+    db: Db,
+) -> impl Future<Item = impl warp::Reply, Error = warp::Rejection> + Send {
     info!("wait_job: id={}", id);
 
-    let mut jobs = db.lock().unwrap();
-    if jobs.contains(id) {
-        &jobs[id].wait();
-        // respond with a `204 No Content`, which means successful,
-        // yet no body expected...
-        Ok(warp::http::StatusCode::NO_CONTENT)
-    } else {
-        debug!("    -> job id not found!");
-        // Reject this request with a `404 Not Found`...
-        Err(warp::reject::not_found())
-    }
+    let done = match db.lock().unwrap().get_mut(id) {
+        Some(job) if job.is_finished() => Ok(None),
+        Some(job) => Ok(Some(job.completion())),
+        None => {
+            debug!("    -> job id not found!");
+            // Reject this request with a `404 Not Found`...
+            Err(warp::reject::not_found())
+        }
+    };
+
+    future::result(done).and_then(move |done| {
+        let wait = match done {
+            Some(done) => future::Either::A(done.then(|_| Ok(()))),
+            None => future::Either::B(future::ok(())),
+        };
+        wait.and_then(move |_| {
+            // the job could have been deleted in the meantime
+            match db.lock().unwrap().get(id) {
+                // respond with resource usage of the finished job
+                Some(job) => Ok(warp::reply::json(&job.usage)),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+    })
 }
 // wait job:1 ends here
 
@@ -516,7 +1076,8 @@ fn wait_job(id: JobId, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
 
 // [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*core][core:1]]
 impl Server {
-    fn serve(&self) {
+    /// Serve requests until shutdown.
+    pub fn serve(&self) {
         // These are some `Filter`s that several of the endpoints share,
         // so we'll define them here and reuse them below...
 
@@ -525,6 +1086,10 @@ impl Server {
         let db = Arc::new(Mutex::new(Jobs::new()));
         let db = warp::any().map(move || db.clone());
 
+        // number of jobs running at the same time
+        let slots = self.slots;
+        let slots = warp::any().map(move || slots);
+
         // Just the path segment "jobs"...
         let jobs = warp::path("jobs");
 
@@ -541,6 +1106,15 @@ impl Server {
         // jobs/:id/files
         let job_dir = path!("jobs" / JobId / "files").and(warp::path::end());
 
+        // jobs/:id/processes
+        let job_procs = path!("jobs" / JobId / "processes").and(warp::path::end());
+
+        // jobs/:id/status
+        let job_state = path!("jobs" / JobId / "status").and(warp::path::end());
+
+        // jobs/:id/priority
+        let job_priority = path!("jobs" / JobId / "priority").and(warp::path::end());
+
         // jobs/:id/files/job.out
         let job_file = path!("jobs" / JobId / "files" / String).and(warp::path::end());
 
@@ -564,6 +1138,7 @@ impl Server {
             .and(jobs_index)
             .and(json_body)
             .and(db.clone())
+            .and(slots.clone())
             .and_then(create_job);
 
         // `PUT /jobs/:id`
@@ -571,12 +1146,21 @@ impl Server {
             .and(job_id)
             .and(json_body)
             .and(db.clone())
+            .and(slots.clone())
             .and_then(update_job);
 
+        // `PUT /jobs/:id/priority`
+        let priority = warp::put2()
+            .and(job_priority)
+            .and(warp::body::content_length_limit(1024).and(warp::body::json()))
+            .and(db.clone())
+            .and_then(update_job_priority);
+
         // `DELETE /jobs/:id`
         let delete = warp::delete2()
             .and(job_id)
             .and(db.clone())
+            .and(slots.clone())
             .and_then(delete_job);
 
         // `GET` /jobs/:id/files
@@ -585,6 +1169,18 @@ impl Server {
             .and(db.clone())
             .and_then(list_job_files);
 
+        // `GET` /jobs/:id/processes
+        let list_procs = warp::get2()
+            .and(job_procs)
+            .and(db.clone())
+            .and_then(list_job_processes);
+
+        // `GET` /jobs/:id/status
+        let status = warp::get2()
+            .and(job_state)
+            .and(db.clone())
+            .and_then(job_status);
+
         // `GET /jobs/:id`
         let wait = warp::get2().and(job_id).and(db.clone()).and_then(wait_job);
 
@@ -605,10 +1201,13 @@ impl Server {
         let api = list
             .or(create)
             .or(update)
+            .or(priority)
             .or(delete)
             .or(wait)
             .or(shutdown)
             .or(list_dir)
+            .or(list_procs)
+            .or(status)
             .or(get_file)
             .or(put_file);
 
@@ -649,3 +1248,121 @@ pub fn bind(addr: &str) {
     server.serve();
 }
 // core:1 ends here
+
+// test
+
+// [[file:~/Workspace/Programming/gosh-rs/runners/runners.note::*test][test:1]]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(seq: usize, priority: i32, status: JobStatus) -> Job {
+        let mut job = Job::new("true").with_priority(priority);
+        job.seq = seq;
+        job.status = status;
+        job
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let mut jobs = Jobs::new();
+        let a = jobs.insert(job(0, 0, JobStatus::NotStarted));
+        let b = jobs.insert(job(1, 10, JobStatus::NotStarted));
+        let c = jobs.insert(job(2, 0, JobStatus::NotStarted));
+        let d = jobs.insert(job(3, 10, JobStatus::NotStarted));
+        let e = jobs.insert(job(4, -5, JobStatus::NotStarted));
+        jobs.insert(job(5, 100, JobStatus::Running));
+        jobs.insert(job(6, 100, JobStatus::Success));
+        assert_eq!(queue(&jobs), vec![b, d, a, c, e]);
+
+        // order by submission, not by reused slab ids
+        jobs.remove(a);
+        let f = jobs.insert(job(7, 0, JobStatus::NotStarted));
+        assert_eq!(f, a);
+        assert_eq!(queue(&jobs), vec![b, d, c, f, e]);
+
+        assert_eq!(job_info(&jobs, c).queue_position, Some(3));
+        assert_eq!(job_info(&jobs, 5).queue_position, None);
+    }
+
+    #[test]
+    fn test_dependency_cancellation() {
+        let mut jobs = Jobs::new();
+        let a = jobs.insert(job(0, 0, JobStatus::Failure(1)));
+        let b = jobs.insert(job(1, 0, JobStatus::NotStarted).with_after_ok(a));
+        let c = jobs.insert(job(2, 0, JobStatus::NotStarted).with_after_ok(b));
+        let d = jobs.insert(job(3, 0, JobStatus::NotStarted).with_after_fail(a));
+        let e = jobs.insert(job(4, 0, JobStatus::NotStarted).with_after_any(a));
+        let f = jobs.insert(job(5, 0, JobStatus::Success));
+        let g = jobs.insert(job(6, 0, JobStatus::NotStarted).with_after_fail(f));
+        let h = jobs.insert(job(7, 0, JobStatus::Running));
+        let i = jobs.insert(job(8, 0, JobStatus::NotStarted).with_after_ok(h));
+        let j = jobs.insert(job(9, 0, JobStatus::NotStarted).with_after_fail(b));
+
+        cancel_unsatisfiable(&mut jobs);
+        assert_eq!(jobs[b].status, JobStatus::Cancelled);
+        // cancellation propagates to dependents of cancelled jobs
+        assert_eq!(jobs[c].status, JobStatus::Cancelled);
+        assert!(jobs[c].reason.as_ref().unwrap().contains("Cancelled"));
+        assert_eq!(jobs[g].status, JobStatus::Cancelled);
+        assert!(jobs[g].is_finished());
+
+        // a cancelled job satisfies after_fail
+        assert_eq!(jobs[d].status, JobStatus::NotStarted);
+        assert_eq!(jobs[e].status, JobStatus::NotStarted);
+        assert_eq!(jobs[j].status, JobStatus::NotStarted);
+        // still waiting for the running job
+        assert_eq!(jobs[i].status, JobStatus::NotStarted);
+        assert_eq!(queue(&jobs), vec![d, e, j]);
+
+        // cancel waiting dependents once the job fails
+        jobs[h].status = JobStatus::Failure(2);
+        cancel_unsatisfiable(&mut jobs);
+        assert_eq!(jobs[i].status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_job_failed_to_start() {
+        let limits = ResourceLimits {
+            max_open_files: Some(u64::max_value()),
+            ..ResourceLimits::default()
+        };
+        let mut jobs = Jobs::new();
+        let mut a = job(0, 0, JobStatus::NotStarted).with_limits(limits);
+        a.build();
+        let a = jobs.insert(a);
+        let b = jobs.insert(job(1, 0, JobStatus::NotStarted).with_after_ok(a));
+
+        let db = Arc::new(Mutex::new(Jobs::new()));
+        dispatch(&mut jobs, &db, 1);
+        assert_eq!(jobs[a].status, JobStatus::Failure(-1));
+        assert!(jobs[a].reason.as_ref().unwrap().contains("failed to start"));
+        assert_eq!(jobs[b].status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_dependency_on_reused_id() {
+        let mut jobs = Jobs::new();
+        let a = jobs.insert(job(0, 0, JobStatus::Success));
+        let b = jobs.insert(job(1, 0, JobStatus::Running));
+        let c = jobs.insert(job(2, 0, JobStatus::NotStarted).with_after_ok(a));
+
+        // the dependency is deleted, and its id taken by a later job, which
+        // must not satisfy the dependency
+        jobs.remove(a);
+        let d = jobs.insert(job(3, 0, JobStatus::Success));
+        assert_eq!(d, a);
+        let e = jobs.insert(job(4, 0, JobStatus::NotStarted).with_after_ok(d));
+        // depending on a job that never existed
+        let f = jobs.insert(job(5, 0, JobStatus::NotStarted).with_after_any(42));
+
+        cancel_unsatisfiable(&mut jobs);
+        assert_eq!(jobs[c].status, JobStatus::Cancelled);
+        assert!(jobs[c].reason.as_ref().unwrap().contains("not found"));
+        assert_eq!(jobs[f].status, JobStatus::Cancelled);
+        assert_eq!(jobs[e].status, JobStatus::NotStarted);
+        assert_eq!(jobs[b].status, JobStatus::Running);
+        assert_eq!(queue(&jobs), vec![e]);
+    }
+}
+// test:1 ends here